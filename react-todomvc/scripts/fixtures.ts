@@ -0,0 +1,82 @@
+// Writes documents made by `AutomergeTodos` to the Rust application's test
+// fixtures, along with the todos each one should decode to, so the Rust
+// tests catch any drift between the two clients' formats.
+//
+//     npm run fixtures
+import * as fs from 'fs';
+import * as path from 'path';
+import * as automerge from 'automerge';
+import { AutomergeTodos, initDoc } from '../src/automerge_hooks';
+
+const OUT = path.join(__dirname, '..', '..', '..', 'vgtk-todomvc', 'tests', 'fixtures', 'js');
+
+async function write(name: string, todos: AutomergeTodos) {
+  fs.mkdirSync(OUT, { recursive: true });
+  fs.writeFileSync(path.join(OUT, `${name}.automerge`), todos.getChanges());
+  const expected = todos.todos.map(todo => ({
+    id: todo.id,
+    value: todo.value,
+    completed: !!todo.completed,
+  }));
+  fs.writeFileSync(
+    path.join(OUT, `${name}.json`),
+    JSON.stringify(expected, null, 2) + '\n'
+  );
+}
+
+async function withTodos(...values: string[]): Promise<AutomergeTodos> {
+  let todos = new AutomergeTodos(initDoc());
+  for (const value of values) {
+    todos = await todos.addNewTodo(value);
+  }
+  return todos;
+}
+
+async function main() {
+  const added = await withTodos('Buy milk', 'Walk the dog', 'Write tests');
+  await write('add', added);
+
+  const toggled = await added.changeTodo(added.todos[1].id, todo => {
+    todo.completed = true;
+  });
+  await write('toggle', toggled);
+
+  const edited = await toggled.changeTodo(toggled.todos[0].id, todo => {
+    todo.value = 'Buy oat milk';
+  });
+  await write('edit', edited);
+
+  let cleared = await withTodos('one', 'two', 'three', 'four');
+  for (const index of [0, 2]) {
+    cleared = await cleared.changeTodo(cleared.todos[index].id, todo => {
+      todo.completed = true;
+    });
+  }
+  await write('clear_completed', await cleared.clearCompletedTodos());
+
+  // Two people edit the same list at once and then sync both ways
+  const shared = await withTodos('shared', 'also shared');
+  // Loading gives each of them their own actor, as on their own machines
+  const saved = shared.getChanges() as automerge.BinaryDocument;
+  let alice = new AutomergeTodos(automerge.load(saved));
+  let bob = new AutomergeTodos(automerge.load(saved));
+  alice = await alice.addNewTodo('from alice');
+  alice = await alice.changeTodo(alice.todos[0].id, todo => {
+    todo.completed = true;
+  });
+  bob = await bob.addNewTodo('from bob');
+  bob = await bob.changeTodo(bob.todos[0].id, todo => {
+    todo.value = 'renamed by bob';
+  });
+  bob = await bob.changeTodo(bob.todos[1].id, todo => {
+    todo.completed = true;
+  });
+  bob = await bob.clearCompletedTodos();
+  alice = await alice.applyChanges(bob.getChanges() as automerge.BinaryDocument);
+  await write('concurrent', alice);
+}
+
+main().catch(e => {
+  console.error(e);
+  process.exit(1);
+});
@@ -20,7 +20,7 @@ type TodoApp = {
   todos: TodoType[]
 }
 
-function initDoc(): Doc<TodoApp>{
+export function initDoc(): Doc<TodoApp>{
   let changeFn = (doc: TodoApp) => {
     doc.todos = []
   }
@@ -34,7 +34,7 @@ function initDoc(): Doc<TodoApp>{
 }
 
 
-class AutomergeTodos {
+export class AutomergeTodos {
   private _doc: Doc<TodoApp>
 
   constructor(doc: Doc<{todos: TodoType[]}>) {
@@ -42,7 +42,7 @@ class AutomergeTodos {
   }
 
   get todos(): TodoType[] {
-    return this._doc.todos
+    return this._doc.todos.filter(todo => todo.deletedAt == null)
   }
 
   async changeTodo(todoId: string, changeFn: (todo: TodoType) =>  void): Promise<AutomergeTodos> {
@@ -68,17 +68,14 @@ class AutomergeTodos {
   }
 
   async clearCompletedTodos(): Promise<AutomergeTodos> {
+    // Moved to the trash rather than deleted, like the GTK client does, so
+    // they can still be restored there
     const newDoc = automerge.change(this._doc, doc => {
-      let i = 0;
-      let numTodos = doc.todos.length
-      while (i < numTodos) {
-          const todo = doc.todos[i]
-          if (todo.completed) {
-              delete doc.todos[i]
-              numTodos -= 1
-          } else {
-              i++
-          }
+      const now = Date.now()
+      for (const todo of doc.todos) {
+        if (todo.completed && todo.deletedAt == null) {
+          todo.deletedAt = now
+        }
       }
     })
     return new AutomergeTodos(newDoc)
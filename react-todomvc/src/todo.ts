@@ -2,4 +2,7 @@ export type TodoType = {
   value: string;
   completed?: boolean;
   id: string;
+  // Set, to milliseconds since the epoch, when the GTK client moves the todo
+  // to its trash
+  deletedAt?: number;
 };
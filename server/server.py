@@ -1,27 +1,221 @@
+import fnmatch
+import hashlib
+import json
+import pathlib
+import queue
+import struct
+import threading
+
 import flask
 from flask_cors import CORS
-import pathlib
+from flask_sock import Sock
+from werkzeug.exceptions import HTTPException
+from werkzeug.security import check_password_hash
+from werkzeug.utils import safe_join
 
 app = flask.Flask("databoy")
 CORS(app)
+sock = Sock(app)
+
+DATA_DIR = "./data"
+TOKENS = pathlib.Path("./tokens.json")
+USERS = pathlib.Path("./users.json")
+
+
+def load_json(path):
+    """The contents of `path`, or None if there's no such file"""
+    if not path.exists():
+        return None
+    return json.loads(path.read_text())
+
+
+def permissions():
+    """The permissions the request's credentials grant, or None if access
+    control is off.
+
+    Bearer tokens are looked up in `./tokens.json`, which looks like
+
+        {"<token>": {"read": ["<document pattern>", ...], "write": [...]}}
+
+    and basic credentials in `./users.json`, which looks like
+
+        {"<user>": {"password": "<hash>", "read": [...], "write": [...]}}
+
+    where the hash is one made by werkzeug's `generate_password_hash`.
+    Patterns are shell style globs such as `shopping` or `team-*`. Without
+    either file anyone can read and write anything."""
+    tokens = load_json(TOKENS)
+    users = load_json(USERS)
+    if tokens is None and users is None:
+        return None
+    header = flask.request.headers.get("Authorization", "")
+    if header.startswith("Bearer "):
+        return (tokens or {}).get(header[len("Bearer "):].strip(), {})
+    credentials = flask.request.authorization
+    if credentials is not None and credentials.type == "basic":
+        user = (users or {}).get(credentials.username or "", {})
+        if check_password_hash(user.get("password", ""), credentials.password or ""):
+            return user
+    return {}
+
+
+def document_name(filename):
+    """The document `filename` belongs to: the signatures stored alongside
+    a document in `<document>.sig` go with the document itself"""
+    if filename.endswith(".sig"):
+        return filename[:-len(".sig")]
+    return filename
+
+
+def allowed(filename, access):
+    """Whether the request's credentials grant `access`, "read" or "write",
+    to the document `filename` belongs to"""
+    granted = permissions()
+    if granted is None:
+        return True
+    document = document_name(filename)
+    return any(fnmatch.fnmatchcase(document, pattern) for pattern in granted.get(access, []))
+
+
+def check(filename, access):
+    """Aborts the request unless `filename` names a file inside `./data`
+    and the request may access it, returns its path"""
+    path = safe_join(DATA_DIR, filename)
+    if path is None:
+        flask.abort(404)
+    if not allowed(filename, access):
+        flask.abort(401 if "Authorization" not in flask.request.headers else 403)
+    return pathlib.Path(path)
+
 
 @app.route("/<path:filename>", methods=["GET"])
 def get_file(filename):
-    path: pathlib.Path = pathlib.Path("./data") / filename
-    if not path.exists():
+    path = check(filename, "read")
+    if not path.is_file():
         return flask.make_response("not found", 404)
-    return flask.send_from_directory("./data", filename, cache_timeout=0)
+    return flask.send_from_directory(DATA_DIR, filename, cache_timeout=0)
 
 
 @app.route("/<path:filename>", methods=["POST"])
 def upload_file(filename):
-    with open(f"./data/{filename}", "wb") as outfile:
+    path = check(filename, "write")
+    path.parent.mkdir(parents=True, exist_ok=True)
+    with open(path, "wb") as outfile:
         data = flask.request.stream.read()
         outfile.write(data)
     return "done"
 
 
-if __name__ == "__main__":
-    app.run(debug=True)
+class LiveDocument:
+    """Every change streamed for a document, in the order we received them,
+    persisted to `./data/<filename>.live` as length prefixed records"""
+
+    def __init__(self, path):
+        self.path = pathlib.Path(f"{path}.live")
+        self.changes = []
+        self.seen = set()
+        self.subscribers = []
+        if self.path.exists():
+            data = self.path.read_bytes()
+            offset = 0
+            while offset + 4 <= len(data):
+                (length,) = struct.unpack_from("<I", data, offset)
+                change = data[offset + 4:offset + 4 + length]
+                if len(change) < length:
+                    break
+                self.changes.append(change)
+                self.seen.add(hashlib.sha256(change).digest())
+                offset += 4 + length
+            # A record cut short by a crash would swallow the start of the
+            # next one appended after it, so drop it
+            if offset < len(data):
+                with open(self.path, "r+b") as livefile:
+                    livefile.truncate(offset)
+
+    def add(self, change):
+        """Records and broadcasts a change, unless we already have it"""
+        digest = hashlib.sha256(change).digest()
+        if digest in self.seen:
+            return
+        self.seen.add(digest)
+        self.changes.append(change)
+        self.path.parent.mkdir(parents=True, exist_ok=True)
+        with open(self.path, "ab") as outfile:
+            outfile.write(struct.pack("<I", len(change)) + change)
+        for subscriber in self.subscribers:
+            subscriber.put(change)
+
 
+def send_queued(ws, changes):
+    """Sends each change put in `changes` until it's given `None`. Sending
+    happens on a thread of its own so that a slow client only holds itself
+    up, never the lock or the other clients."""
+    while True:
+        change = changes.get()
+        if change is None:
+            return
+        try:
+            ws.send(change)
+        except Exception:
+            return
 
+
+live_documents = {}
+live_lock = threading.Lock()
+
+
+@sock.route("/live/<path:filename>")
+def live(ws, filename):
+    """Streams a document's changes: on connect we send every change after
+    the first `since` the client has already seen, then each change any
+    client sends us is stored and sent on to everyone, the sender included,
+    so that every client sees the same order and can count its place in it.
+
+    Connecting needs read access to the document, and what a client sends
+    is only stored if it has write access too."""
+    try:
+        path = check(filename, "read")
+    except HTTPException as e:
+        # The handshake is already done, so close with "policy violation"
+        ws.close(reason=1008, message=e.description)
+        return
+    can_write = allowed(filename, "write")
+    try:
+        since = int(flask.request.args.get("since", 0))
+    except ValueError:
+        since = -1
+    if since < 0:
+        ws.close(reason=1008, message="since must be a count of changes")
+        return
+    # Queueing the backlog and subscribing under the lock means the client
+    # gets the changes in the order they were stored, with none missed or
+    # repeated, without sending anything while holding the lock
+    changes = queue.Queue()
+    with live_lock:
+        if filename not in live_documents:
+            live_documents[filename] = LiveDocument(path)
+        document = live_documents[filename]
+        for change in document.changes[since:]:
+            changes.put(change)
+        document.subscribers.append(changes)
+    sender = threading.Thread(target=send_queued, args=(ws, changes), daemon=True)
+    sender.start()
+    try:
+        while True:
+            change = ws.receive()
+            if not isinstance(change, bytes):
+                continue
+            if not can_write:
+                ws.close(reason=1008, message=f"no write access to {filename}")
+                return
+            with live_lock:
+                document.add(change)
+    finally:
+        with live_lock:
+            if changes in document.subscribers:
+                document.subscribers.remove(changes)
+        changes.put(None)
+
+
+if __name__ == "__main__":
+    app.run(debug=True)
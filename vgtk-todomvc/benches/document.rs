@@ -0,0 +1,87 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use todomvc::document::Document;
+use todomvc::items::{Item, PAGE_SIZE};
+
+const SIZES: &[usize] = &[1_000, 10_000];
+
+/// A saved document with `n` items, every other one completed
+fn generate(n: usize) -> Vec<u8> {
+    let mut document = Document::default();
+    for i in 0..n {
+        document.add(&Item::new(format!("Task {}", i)));
+    }
+    let ids: Vec<String> = document.items().iter().step_by(2).map(|i| i.id.clone()).collect();
+    document.toggle_all(&ids);
+    document.save().unwrap()
+}
+
+fn bench_operations(c: &mut Criterion) {
+    for &n in SIZES {
+        let saved = generate(n);
+        let load = || Document::load(saved.clone()).unwrap();
+
+        c.bench_function(&format!("add/{}", n), |b| {
+            b.iter_batched(load, |mut doc| doc.add(&Item::new("one more")), BatchSize::LargeInput)
+        });
+
+        c.bench_function(&format!("toggle_all/{}", n), |b| {
+            b.iter_batched(
+                || {
+                    let doc = load();
+                    let ids: Vec<String> = doc.items().iter().map(|i| i.id.clone()).collect();
+                    (doc, ids)
+                },
+                |(mut doc, ids)| doc.toggle_all(&ids),
+                BatchSize::LargeInput,
+            )
+        });
+
+        c.bench_function(&format!("clear_completed/{}", n), |b| {
+            b.iter_batched(load, |mut doc| doc.clear_completed(), BatchSize::LargeInput)
+        });
+
+        // The items the list builds rows for, when it's first shown and once
+        // it's been scrolled to the end. Rows are paged rather than
+        // virtualized, so the second is what a list scrolled all the way
+        // through costs on every render.
+        c.bench_function(&format!("first_page/{}", n), |b| {
+            let doc = load();
+            b.iter(|| doc.items().page(PAGE_SIZE, |item| !item.is_deleted()))
+        });
+
+        c.bench_function(&format!("every_page/{}", n), |b| {
+            let doc = load();
+            b.iter(|| doc.items().page(n, |item| !item.is_deleted()))
+        });
+
+        c.bench_function(&format!("save/{}", n), |b| {
+            let doc = load();
+            b.iter(|| doc.save().unwrap())
+        });
+
+        c.bench_function(&format!("merge/{}", n), |b| {
+            // Two peers which each added 100 items to the same base
+            let mut ours = load();
+            let mut theirs = load();
+            for i in 0..100 {
+                ours.add(&Item::new(format!("Ours {}", i)));
+                theirs.add(&Item::new(format!("Theirs {}", i)));
+            }
+            let ours = ours.save().unwrap();
+            let theirs = automerge::Change::load_document(&theirs.save().unwrap()).unwrap();
+            b.iter_batched(
+                || (Document::load(ours.clone()).unwrap(), theirs.clone()),
+                |(mut doc, changes)| doc.apply_changes(changes).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_operations
+}
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+//! Applying a single change streamed by live or peer to peer sync
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use todomvc::document::{self, Document};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(change) = document::decode_change(data.to_vec()) {
+        let mut document = Document::default();
+        if document.apply_changes(vec![change]).is_ok() {
+            document.items().problems();
+        }
+    }
+});
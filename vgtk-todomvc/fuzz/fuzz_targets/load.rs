@@ -0,0 +1,11 @@
+//! Opening a saved document, e.g. one pulled from a remote
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use todomvc::document::Document;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(document) = Document::load(data.to_vec()) {
+        document.items().problems();
+    }
+});
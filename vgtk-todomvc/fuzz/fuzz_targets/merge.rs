@@ -0,0 +1,15 @@
+//! Merging whatever a remote sent into a document with some history
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use todomvc::document::Document;
+use todomvc::items::Item;
+use todomvc::signing::{Keyring, Signatures};
+
+fuzz_target!(|data: &[u8]| {
+    let mut document = Document::default();
+    document.add(&Item::new("ours"));
+    if document.merge_verified(data, &Signatures::new(), &Keyring::default()).is_ok() {
+        document.items().problems();
+    }
+});
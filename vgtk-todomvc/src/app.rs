@@ -1,27 +1,47 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::convert::TryInto;
-use std::str::FromStr;
+use std::thread;
 
-use vgtk::lib::gio::{ActionExt, ApplicationFlags, File, FileExt, SimpleAction};
-use vgtk::lib::glib::Error;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+
+use vgtk::lib::gio::{self, ActionExt, ActionGroupExt, ApplicationFlags, File, FileExt, SimpleAction};
+use vgtk::lib::glib::{self, Error, StaticType, ToVariant, VariantTy};
 use vgtk::lib::gtk::prelude::*;
 use vgtk::lib::gtk::*;
 use vgtk::{ext::*, gtk, gtk_if, on_signal, Component, UpdateAction, VNode};
 
 use strum_macros::{Display, EnumIter};
 
+use todomvc::compact::{self, SQUASH_WARNING};
+use todomvc::config;
+use todomvc::dbus::{self, Call, Reply};
+use todomvc::document::{self, Document};
+use todomvc::items::{Item, Items, DUE_FORMAT, PAGE_SIZE};
+use todomvc::journal::{self, Journal};
+use todomvc::live::{self, LiveEvent};
+use todomvc::outbox::{Outbox, Pending};
+use todomvc::p2p;
+use todomvc::preview::{self, Preview};
+use todomvc::reminders::{Changes, Reminders, Stage, SystemClock};
+use todomvc::shortcuts::{Bindings, SHORTCUTS};
+use todomvc::signing::{Flagged, Keyring, Signatures, Signer};
+use todomvc::status::{self, Attempt, SyncStatus};
+use todomvc::remote::Remote;
+
 use crate::about::AboutDialog;
 use crate::radio::Radio;
-use crate::items::{Item, Items};
-
-use maplit::hashmap;
+use crate::rows::{item_row, peer_row, problem_row, remote_row, ITEM_ROW};
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug, Display, EnumIter)]
 pub enum Filter {
     All,
     Active,
     Completed,
+    Trash,
 }
 
 impl Default for Filter {
@@ -30,93 +50,534 @@ impl Default for Filter {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Remote {
-    url: String,
-}
-
-impl Remote {
-    fn render(&self, index: usize) -> VNode<Model> {
-        let url = self.url.clone();
-        gtk!{
-            <Box spacing=10 orientation=Orientation::Horizontal>
-                <Label label=self.url.to_string() />
-                <Button label="pull" on clicked=|_| { Msg::PullFromRemote { remote_index: index }}/>
-                <Button label="push" on clicked=|_| { Msg::PushToRemote { remote_index: index }}/>
-            </Box>
+impl Filter {
+    fn matches(self, item: &Item) -> bool {
+        match self {
+            Filter::All => !item.is_deleted(),
+            Filter::Active => !item.done && !item.is_deleted(),
+            Filter::Completed => item.done && !item.is_deleted(),
+            Filter::Trash => item.is_deleted(),
         }
     }
 }
 
+/// How often to check for pushes in the outbox which are due to be retried
+const RETRY_TICK: u32 = 15;
+
+/// How often to check for tasks which have become due, including ones which
+/// have just been pulled
+const REMINDER_TICK: u32 = 30;
+
+/// One open todo list, with everything which goes with it
 #[derive(Clone)]
-pub struct Model {
+struct Tab {
+    /// Stays the same while the tab is open, unlike its index, so replies to
+    /// deferred work find their way back to it
+    id: usize,
     filter: Filter,
     file: Option<File>,
     clean: bool,
     remotes: Vec<Remote>,
-    backend: Arc<Mutex<automerge::Backend>>,
-    frontend: Arc<Mutex<automerge::Frontend>>,
+    /// How syncing with each remote has gone, by location
+    sync_status: HashMap<String, SyncStatus>,
+    document: Arc<Mutex<Document>>,
+    /// How many rows of the current filter are built. The list is paged,
+    /// not virtualized: another page is built each time the user scrolls to
+    /// the bottom, and built rows are kept until the filter changes.
+    shown_rows: usize,
+    /// The state of each live sync and peer to peer connection, by the
+    /// WebSocket URL or peer address its events are tagged with
+    live_status: HashMap<String, String>,
+    /// Every peer we've connected to or which has connected to us
+    peers: Vec<String>,
+    /// The address we're accepting peers on, if we are
+    sharing: Option<String>,
+    /// Set when the tab is closed, which stops its live sync and peer to
+    /// peer connections
+    closed: Arc<AtomicBool>,
+    /// The id of the item the keyboard is on, or was on last
+    cursor: Option<String>,
+    /// The id of the item being edited, if one is
+    editing: Option<String>,
+}
+
+impl Tab {
+    fn new(id: usize, document: Document) -> Tab {
+        Tab {
+            id,
+            filter: Filter::All,
+            file: None,
+            clean: true,
+            remotes: Vec::new(),
+            sync_status: HashMap::new(),
+            document: Arc::new(Mutex::new(document)),
+            shown_rows: PAGE_SIZE,
+            live_status: HashMap::new(),
+            peers: Vec::new(),
+            sharing: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            cursor: None,
+            editing: None,
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.file.as_ref().and_then(|p| p.get_basename()) {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => "Untitled todo list".to_string(),
+        }
+    }
+
+    fn is_autosaved(&self) -> bool {
+        self.document.lock().unwrap().is_autosaved()
+    }
+
+    /// The autosave directory which identifies the list in the outbox
+    fn list(&self) -> Option<PathBuf> {
+        self.document.lock().unwrap().autosave_dir().map(Path::to_path_buf)
+    }
+
+    /// Whether quitting would lose changes. An autosaved list keeps its
+    /// changes whether it's saved to a file or not, and comes back next time.
+    fn unsaved(&self) -> bool {
+        !self.clean && !self.is_autosaved()
+    }
+
+    /// Whether closing the tab would lose anything. Closing throws its
+    /// autosave away, so that's any changes since it was last saved to a
+    /// file, or all of it if it never was.
+    fn lost_on_close(&self) -> bool {
+        !self.clean || (self.file.is_none() && !self.items().is_empty())
+    }
+
+    fn filter(&self, filter: Filter) -> Vec<Item> {
+        let items = self.items();
+        items.iter().filter(move |item| filter.matches(item)).cloned().collect::<Vec<Item>>()
+    }
+
+    fn count(&self, filter: Filter) -> usize {
+        self.items().iter().filter(move |item| filter.matches(item)).count()
+    }
+
+    /// The items of the current filter on the pages built so far
+    fn shown_items(&self) -> Vec<Item> {
+        let filter = self.filter;
+        self.items().page(self.shown_rows, |item| filter.matches(item))
+    }
+
+    fn items(&self) -> Arc<Items> {
+        self.document.lock().unwrap().items()
+    }
+
+    fn left_label(&self) -> String {
+        let left = self.count(Filter::Active);
+        match left {
+            1 => String::from("1 item left"),
+            left => format!("{} items left", left),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Model {
+    tabs: Vec<Tab>,
+    /// The index of the tab being shown
+    current: usize,
+    next_tab_id: usize,
+    /// Where each list is autosaved, in a directory of its own, if
+    /// autosave is on
+    autosave: Option<PathBuf>,
+    /// Signs the changes made to every list
+    signer: Option<Arc<Signer>>,
+    /// Pushes which failed and are waiting to be retried
+    outbox: Arc<Mutex<Outbox>>,
+    /// Whether a retry of the outbox is scheduled
+    retry_scheduled: bool,
+    /// Which due tasks have been notified about and which snoozed
+    reminders: Arc<Mutex<Reminders<SystemClock>>>,
+    /// Names this installation to remotes which keep a copy per peer
+    peer: String,
     new_remote_buffer: EntryBuffer,
+    /// Encrypts what's pushed to the remote being added, if it's filled in
+    new_passphrase_buffer: EntryBuffer,
+    error: Option<String>,
+    /// Where every live sync connection sends its events
+    /// Live sync and peer to peer events, tagged with the id of their tab
+    live_sender: UnboundedSender<(usize, String, LiveEvent)>,
+    live_receiver: Arc<futures::lock::Mutex<UnboundedReceiver<(usize, String, LiveEvent)>>>,
+    share_port_buffer: EntryBuffer,
+    peer_address_buffer: EntryBuffer,
+    peer_code_buffer: EntryBuffer,
+    /// Whether we're waiting on `live_receiver`
+    listening: bool,
+    /// Where the D-Bus interface is served, if it could be
+    dbus: Option<zbus::Connection>,
+    /// Calls to the D-Bus interface waiting to be answered
+    dbus_receiver: Arc<futures::lock::Mutex<UnboundedReceiver<dbus::Request>>>,
+    /// The items D-Bus clients were last told about, so they're only told
+    /// again when they've changed
+    announced: Option<Arc<Items>>,
+    /// The keys bound to each action
+    shortcuts: Bindings,
+    /// Whether the keyboard is on an item right now, rather than e.g. typing
+    /// in an entry, so keys like space act on the item
+    on_item: bool,
+    /// Whether to move the keyboard to the new item entry
+    focus_new_item: bool,
 }
 
 impl std::fmt::Debug for Model {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Model(items: {:?}, filter: {:?}, file: {:?}, clean: {:?}, remotes: {:?})", self.items(), self.filter, self.file, self.clean, self.remotes)
+        let tab = self.tab();
+        write!(f, "Model(items: {:?}, filter: {:?}, file: {:?}, clean: {:?}, remotes: {:?}, tabs: {})", tab.items(), tab.filter, tab.file, tab.clean, tab.remotes, self.tabs.len())
     }
 }
 
 impl Default for Model {
     fn default() -> Self {
-        let mut backend = automerge::Backend::init();
-        let change = automerge::Change::from_bytes(crate::INIT_CHANGE.to_vec()).unwrap();
-        let patch = backend.apply_changes(vec![change]).unwrap();
-        let mut frontend = automerge::Frontend::new();
-        frontend.apply_patch(patch).unwrap();
+        let autosave = Journal::default_dir();
+        let recovered = autosave
+            .as_ref()
+            .ok_or_else(|| "Autosave is off: could not find a data directory".to_string())
+            .and_then(|dir| Document::recover_free(dir));
+        let (mut documents, mut error) = match recovered {
+            Ok(documents) => (documents, None),
+            // Don't autosave over whatever we failed to recover
+            Err(e) => (vec![Document::default()], Some(format!("Could not recover autosave: {}", e))),
+        };
+        let signer = match Signer::load_or_create() {
+            Ok(signer) => Some(Arc::new(signer)),
+            Err(e) => {
+                error = error.or_else(|| Some(format!("Changes won't be signed: {}", e)));
+                None
+            }
+        };
+        if let Some(signer) = signer.as_ref() {
+            for document in documents.iter_mut() {
+                document.set_signer(signer.clone());
+            }
+        }
+        let outbox = Outbox::default_path()
+            .ok_or_else(|| "could not find a data directory".to_string())
+            .and_then(|path| Outbox::open(&path))
+            .unwrap_or_else(|e| {
+                error = error.take().or_else(|| Some(format!("Failed pushes won't be retried after a restart: {}", e)));
+                Outbox::in_memory()
+            });
+        // Bring back the remotes we still owe a push, on the list the push is
+        // of, so they're retried. Encrypted ones have to be added again with
+        // their passphrase, and pushes of lists which didn't come back, e.g.
+        // because another instance has them open, stay in the outbox.
+        let credentials = config::load_credentials().unwrap_or_default();
+        let mut tabs: Vec<Tab> = documents
+            .into_iter()
+            .enumerate()
+            .map(|(id, document)| Tab::new(id, document))
+            .collect();
+        for tab in tabs.iter_mut() {
+            let list = tab.list();
+            tab.remotes = outbox
+                .pending()
+                .iter()
+                .filter(|(_, pending)| !pending.encrypted && pending.is_for(list.as_deref()))
+                .filter_map(|(location, _)| Remote::new(location.clone(), credentials.get(location).cloned(), None).ok())
+                .collect();
+        }
+        if !outbox.is_empty() {
+            // Once the application is running and its actions exist
+            glib::idle_add_local(|| {
+                schedule_retry();
+                glib::Continue(false)
+            });
+        }
+        let shortcuts = Bindings::path()
+            .map_or(Ok(Bindings::default()), |path| Bindings::load(&path))
+            .and_then(|shortcuts| check_accels(&shortcuts).map(|_| shortcuts))
+            .unwrap_or_else(|e| {
+                error = error.take().or_else(|| Some(format!("Using the default keyboard shortcuts: {}", e)));
+                Bindings::default()
+            });
+        let (dbus_sender, dbus_receiver) = unbounded();
+        let served = dbus::connect(None).and_then(|connection| {
+            dbus::serve(connection.clone(), dbus::Forward(dbus_sender)).map(|_| connection)
+        });
+        let connection = match served {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                error = error.take().or_else(|| Some(format!("The D-Bus interface is off: {}", e)));
+                None
+            }
+        };
+        let accels = shortcuts.clone();
+        glib::idle_add_local(move || {
+            install_accels(&accels);
+            // Starts checking for due tasks, every REMINDER_TICK from then on
+            if let Some(app) = gio::Application::get_default() {
+                app.activate_action("reminders", None);
+                app.activate_action("dbus", None);
+            }
+            guard_close();
+            glib::Continue(false)
+        });
+        let (live_sender, live_receiver) = unbounded();
+        let announced = Some(tabs[0].items());
         Model {
-            filter: Filter::All,
-            file: None,
-            clean: true,
-            remotes: Vec::new(),
-            backend: Arc::new(Mutex::new(backend)),
-            frontend: Arc::new(Mutex::new(frontend)),
+            next_tab_id: tabs.len(),
+            tabs,
+            current: 0,
+            autosave,
+            signer,
+            outbox: Arc::new(Mutex::new(outbox)),
+            retry_scheduled: false,
+            reminders: Arc::new(Mutex::new(Reminders::new(SystemClock))),
+            peer: config::peer_id().unwrap_or_else(|_| uuid::Uuid::new_v4().simple().to_string()),
             new_remote_buffer: EntryBuffer::new(None),
+            new_passphrase_buffer: EntryBuffer::new(None),
+            error,
+            live_sender,
+            live_receiver: Arc::new(futures::lock::Mutex::new(live_receiver)),
+            share_port_buffer: EntryBuffer::new(None),
+            peer_address_buffer: EntryBuffer::new(None),
+            peer_code_buffer: EntryBuffer::new(None),
+            listening: false,
+            dbus: connection,
+            dbus_receiver: Arc::new(futures::lock::Mutex::new(dbus_receiver)),
+            announced,
+            shortcuts,
+            on_item: false,
+            focus_new_item: false,
         }
     }
 }
 
 impl Model {
-    fn filter(&self, filter: Filter) -> Vec<Item> {
-        let items = self.items();
-        items.iter().filter(move |item| match filter {
-            Filter::All => true,
-            Filter::Active => !item.done,
-            Filter::Completed => item.done,
-        }).cloned().collect::<Vec<Item>>()
+    fn tab(&self) -> &Tab {
+        &self.tabs[self.current]
     }
 
-    fn items(&self) -> Items {
-        let mut frontend = self.frontend.lock().unwrap();
-        let state = frontend.state();
-        state.try_into().unwrap()
+    fn tab_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.current]
     }
 
-    fn left_label(&self) -> String {
-        let left = self.filter(Filter::Active).iter().count();
-        match left {
-            1 => String::from("1 item left"),
-            left => format!("{} items left", left),
+    /// The index of the tab with the given id, if it's still open
+    fn tab_index(&self, id: usize) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.id == id)
+    }
+
+    /// A new empty list, autosaved in a directory of its own if autosave is
+    /// on
+    fn new_document(&mut self) -> Document {
+        let autosaved = self.autosave.as_ref().map(|base| Document::recover(&journal::new_list(base)));
+        match autosaved {
+            Some(Ok(document)) => document,
+            Some(Err(e)) => {
+                self.error = Some(format!("The new list won't be autosaved: {}", e));
+                Document::default()
+            }
+            None => Document::default(),
+        }
+    }
+
+    /// Opens a new tab for `document` and shows it
+    fn open_tab(&mut self, mut document: Document, file: Option<File>) {
+        if let Some(signer) = self.signer.as_ref() {
+            document.set_signer(signer.clone());
+        }
+        let mut tab = Tab::new(self.next_tab_id, document);
+        tab.file = file;
+        self.next_tab_id += 1;
+        self.tabs.push(tab);
+        self.current = self.tabs.len() - 1;
+    }
+
+    /// Pushes a tab's document to one of its remotes, merging what it holds
+    /// first, and records how it went in its sync status and, if it failed,
+    /// in the outbox to be retried
+    fn push(&mut self, tab: usize, remote_index: usize) -> Result<(), String> {
+        let Model { tabs, outbox, peer, error, .. } = self;
+        let tab = &mut tabs[tab];
+        let remote = &tab.remotes[remote_index];
+        let mut document = tab.document.lock().unwrap();
+        let pushed = load_keyring()
+            .and_then(|keyring| remote.push_document(peer, &mut document, &keyring))
+            .map(|flagged| {
+                if !flagged.is_empty() {
+                    *error = Some(describe_flagged(&remote.location, &flagged));
+                }
+            });
+        let ours = document.change_hashes();
+        let list = document.autosave_dir().map(Path::to_path_buf);
+        drop(document);
+        let status = tab.sync_status.entry(remote.location.clone()).or_default();
+        status.last_push = Some(Attempt::now(pushed.as_ref().err().cloned()));
+        let mut outbox = outbox.lock().unwrap();
+        let recorded = match &pushed {
+            Ok(()) => {
+                status.remote_changes = Some(ours);
+                outbox.succeeded(&remote.location, list.as_deref())
+            }
+            Err(e) => outbox.failed(&remote.location, list.as_deref(), remote.is_encrypted(), chrono::Utc::now().timestamp(), e),
+        };
+        if let Err(e) = recorded {
+            *error = Some(format!("Could not save the outbox: {}", e));
+        }
+        pushed
+    }
+
+    /// The tab and remote a push waiting in the outbox is retried with: the
+    /// tab of the list it's a push of, if it's open and has the remote
+    fn tab_for_push(&self, location: &str, pending: &Pending) -> Option<(usize, usize)> {
+        self.tabs.iter().enumerate().find_map(|(index, tab)| {
+            if !pending.is_for(tab.list().as_deref()) {
+                return None;
+            }
+            tab.remotes.iter().position(|remote| remote.location == location).map(|remote| (index, remote))
+        })
+    }
+
+    /// Where the current tab's live sync and peer to peer connections send
+    /// their events. They're passed on tagged with the tab's id until it's
+    /// closed, after which the connections stop the next time they have
+    /// something to send.
+    fn live_events(&self) -> UnboundedSender<(String, LiveEvent)> {
+        let (sender, receiver) = unbounded();
+        let id = self.tab().id;
+        let closed = self.tab().closed.clone();
+        let events = self.live_sender.clone();
+        thread::spawn(move || {
+            for (url, event) in futures::executor::block_on_stream(receiver) {
+                if closed.load(Ordering::SeqCst) || events.unbounded_send((id, url, event)).is_err() {
+                    return;
+                }
+            }
+        });
+        sender
+    }
+
+    /// Waits for the next event from live or peer to peer sync, unless we
+    /// already are
+    fn start_listening(&mut self) -> UpdateAction<Model> {
+        if self.listening {
+            return UpdateAction::Render;
+        }
+        self.listening = true;
+        let receiver = self.live_receiver.clone();
+        UpdateAction::defer(async move {
+            match receiver.lock().await.next().await {
+                Some((tab, url, event)) => Msg::Live { tab, url, event },
+                None => Msg::NoOp,
+            }
+        })
+    }
+
+    fn add(&mut self, task: String) -> String {
+        let new_item = Item::new(task);
+        self.tab().document.lock().unwrap().add(&new_item);
+        self.tab_mut().clean = false;
+        new_item.id
+    }
+
+    /// Answers a call to the D-Bus interface, on the list being shown
+    fn answer(&mut self, call: Call) -> Result<Reply, String> {
+        match call {
+            Call::Sync => {
+                self.handle(Msg::PullAll);
+                self.handle(Msg::PushAll);
+                Ok(Reply::Done)
+            }
+            Call::ListTodos => dbus::answer(&mut self.tab().document.lock().unwrap(), call),
+            call => {
+                let reply = dbus::answer(&mut self.tab().document.lock().unwrap(), call)?;
+                self.tab_mut().clean = false;
+                Ok(reply)
+            }
+        }
+    }
+
+    /// Emits the D-Bus interface's Changed signal if the items in the list
+    /// being shown aren't the ones it was last emitted for
+    fn announce(&mut self) {
+        let connection = match self.dbus.as_ref() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let items = self.tab().items();
+        if self.announced.as_ref().map_or(true, |announced| !Arc::ptr_eq(announced, &items)) {
+            // Nobody listening isn't worth telling the user about
+            let _ = dbus::changed(connection);
+            self.announced = Some(items);
         }
     }
 
-    fn main_panel(&self) -> VNode<Model> {
+    /// Pushes waiting in the outbox which can't be retried: encrypted ones
+    /// from before a restart, and ones of lists which aren't open
+    fn outbox_panel(&self) -> Option<VNode<Model>> {
+        let outbox = self.outbox.lock().unwrap();
+        let orphans: Vec<String> = outbox
+            .pending()
+            .iter()
+            .filter(|(location, pending)| self.tab_for_push(location, pending).is_none())
+            .map(|(location, pending)| {
+                if self.tabs.iter().any(|tab| pending.is_for(tab.list().as_deref())) {
+                    format!("{}: waiting to push, add it again with its passphrase", location)
+                } else {
+                    format!("{}: waiting to push a list which isn't open", location)
+                }
+            })
+            .collect();
+        if orphans.is_empty() {
+            return None;
+        }
+        Some(gtk! {
+            <Label label=orphans.join("\n") line_wrap=true xalign=0.0 />
+        })
+    }
+
+    fn problems_panel(&self, tab: &Tab) -> VNode<Model> {
+        let items = tab.items();
         gtk! {
-            <Box orientation=Orientation::Horizontal spacing=10>
+            <Box spacing=10 orientation=Orientation::Vertical Box::expand=false>
+                <Label label="Problems" xalign=0.0 />
+                <ListBox selection_mode=SelectionMode::None>
+                    {
+                        items.problems().iter().map(problem_row)
+                    }
+                </ListBox>
+            </Box>
+        }
+    }
+
+    /// The error to show above the list, if any
+    fn current_error(&self) -> Option<String> {
+        self.error.clone().or_else(|| {
+            self.tab().document.lock().unwrap().autosave_error().map(str::to_string)
+        })
+    }
+
+    fn error_bar(&self) -> VNode<Model> {
+        let message = self.current_error().unwrap_or_default();
+        gtk! {
+            <Box spacing=10 orientation=Orientation::Horizontal Box::expand=false>
+                <Label label=message line_wrap=true Box::expand=true Box::fill=true />
+                <Button label="Dismiss" Box::pack_type=PackType::End on clicked=|_| Msg::DismissError />
+            </Box>
+        }
+    }
+
+    fn tab_panel(&self, tab: &Tab, index: usize) -> VNode<Model> {
+        let title = if tab.unsaved() { format!("{} *", tab.title()) } else { tab.title() };
+        gtk! {
+            <Box orientation=Orientation::Horizontal spacing=10 border_width=10 Notebook::tab_label=title>
                 <Box spacing=10 orientation=Orientation::Vertical Box::fill=true Box::expand=true>
+                    {
+                        self.current_error().map(|_| self.error_bar())
+                    }
                     <Box spacing=10 orientation=Orientation::Horizontal Box::expand=false>
                         <Button image="edit-select-all" relief=ReliefStyle::Half
                                 always_show_image=true on clicked=|_| Msg::ToggleAll/>
                         <Entry placeholder_text="What needs to be done?"
                                Box::expand=true Box::fill=true
+                               property_has_focus=self.focus_new_item && index == self.current
                                on activate=|entry| {
                                    let label = entry.get_text().to_string();
                                    entry.select_region(0, label.len() as i32);
@@ -125,40 +586,111 @@ impl Model {
                                    }
                                } />
                     </Box>
-                    <ScrolledWindow Box::expand=true Box::fill=true>
+                    <ScrolledWindow Box::expand=true Box::fill=true
+                                    on edge_reached=|_, position| {
+                                        if position == PositionType::Bottom {
+                                            Msg::ShowNextPage
+                                        } else {
+                                            Msg::NoOp
+                                        }
+                                    }>
                         <ListBox selection_mode=SelectionMode::None>
                             {
-                                self.filter(self.filter).iter().enumerate()
-                                    .map(|(index, item)| item.render(index))
+                                tab.shown_items().iter().map(|item| {
+                                    let focused = tab.cursor.as_ref() == Some(&item.id);
+                                    let editing = tab.editing.as_ref() == Some(&item.id);
+                                    item_row(item, focused, editing)
+                                })
                             }
                         </ListBox>
                     </ScrolledWindow>
+                    {
+                        if tab.items().problems().is_empty() { None } else { Some(self.problems_panel(tab)) }
+                    }
                     <Box spacing=10 orientation=Orientation::Horizontal Box::expand=false>
-                        <Label label=self.left_label()/>
-                        <@Radio<Filter> active=self.filter Box::center_widget=true on changed=|filter| Msg::Filter { filter } />
+                        <Label label=tab.left_label()/>
+                        <@Radio<Filter> active=tab.filter Box::center_widget=true on changed=|filter| Msg::Filter { filter } />
                         {
-                            gtk_if!(self.filter(Filter::Completed).iter().count() > 0 => {
+                            gtk_if!(tab.filter != Filter::Trash && tab.count(Filter::Completed) > 0 => {
                                 <Button label="Clear completed" Box::pack_type=PackType::End
                                         on clicked=|_| Msg::ClearCompleted/>
                             })
                         }
+                        {
+                            gtk_if!(tab.filter == Filter::Trash && tab.count(Filter::Trash) > 0 => {
+                                <Button label="Empty trash" Box::pack_type=PackType::End
+                                        on clicked=|_| Msg::EmptyTrash/>
+                            })
+                        }
                     </Box>
                 </Box>
                 <Box spacing=10 orientation=Orientation::Vertical>
-                    <Label label="Remotes" width_chars=50/>  
+                    <Label label="Remotes" width_chars=50/>
                     <Box spacing=10 orientation=Orientation::Horizontal Box::expand=false>
-                        <Entry placeholder_text="Remote url"
-                             buffer=self.new_remote_buffer.clone() 
-                            Box::expand=true 
+                        <Entry placeholder_text="Remote URL or path"
+                             buffer=self.new_remote_buffer.clone()
+                            Box::expand=true
                             on activate=|entry| {
                                 Msg::AddRemote
                             } />
-                        <Button label="Add" 
+                        <Button label="Add"
                                 on clicked=|e|{
-                                    Msg::AddRemote 
+                                    Msg::AddRemote
                                 } />
                     </Box>
-                    {self.remotes.iter().enumerate().map(|(index, remote)| remote.render(index))}
+                    <Entry placeholder_text="Encryption passphrase (optional)"
+                           buffer=self.new_passphrase_buffer.clone()
+                           visibility=false
+                           on activate=|_| Msg::AddRemote />
+                    {
+                        let ours = tab.document.lock().unwrap().change_hashes();
+                        let list = tab.list();
+                        tab.remotes.iter().enumerate().map(move |(index, remote)| {
+                            let live = live::live_url(&remote.location).and_then(|url| tab.live_status.get(&url));
+                            let status = tab.sync_status.get(&remote.location).cloned().unwrap_or_default();
+                            let pending = self
+                                .outbox
+                                .lock()
+                                .unwrap()
+                                .get(&remote.location)
+                                .filter(|pending| pending.is_for(list.as_deref()))
+                                .cloned();
+                            remote_row(remote, index, live.map(String::as_str), &status, pending.as_ref(), &ours)
+                        })
+                    }
+                    {
+                        self.outbox_panel()
+                    }
+                    <Label label="Peers" />
+                    <Box spacing=10 orientation=Orientation::Horizontal Box::expand=false>
+                        <Entry placeholder_text="Port"
+                               buffer=self.share_port_buffer.clone()
+                               sensitive=tab.sharing.is_none()
+                               Box::expand=true
+                               on activate=|_| Msg::SharePort />
+                        <Button label="Share on port" sensitive=tab.sharing.is_none()
+                                on clicked=|_| Msg::SharePort />
+                    </Box>
+                    {
+                        tab.sharing.as_ref().map(|sharing| gtk! {
+                            <Label label=format!("Sharing on {}", sharing) selectable=true />
+                        })
+                    }
+                    <Box spacing=10 orientation=Orientation::Horizontal Box::expand=false>
+                        <Entry placeholder_text="host:port"
+                               buffer=self.peer_address_buffer.clone()
+                               Box::expand=true
+                               on activate=|_| Msg::ConnectToPeer />
+                        <Entry placeholder_text="Pairing code"
+                               buffer=self.peer_code_buffer.clone()
+                               on activate=|_| Msg::ConnectToPeer />
+                        <Button label="Connect" on clicked=|_| Msg::ConnectToPeer />
+                    </Box>
+                    {
+                        tab.peers.iter().map(|peer| {
+                            peer_row(peer, tab.live_status.get(peer).map(String::as_str).unwrap_or("connecting"))
+                        })
+                    }
                 </Box>
             </Box>
         }
@@ -169,17 +701,81 @@ impl Model {
 pub enum Msg {
     NoOp,
     Add { item: String },
-    Remove { index: usize },
-    Toggle { index: usize },
+    Remove { id: String },
+    Restore { id: String },
+    EmptyTrash,
+    Toggle { id: String },
+    Rename { id: String, task: String },
+    /// `due` is empty to clear the due date
+    SetDue { id: String, due: String },
+    /// `rule` is empty to stop the item repeating
+    SetRecurrence { id: String, rule: String },
+    FocusNewItem,
+    FocusChanged { item: Option<String> },
+    MoveCursor { by: isize },
+    ToggleCursor,
+    RemoveCursor,
+    EditCursor,
+    CancelEdit,
+    PullAll,
+    PushAll,
+    ShowShortcuts,
     Filter { filter: Filter },
     ToggleAll,
     ClearCompleted,
+    ShowNextPage,
+    NewList,
+    Open,
+    Opened { file: File, items: Items },
+    Save,
+    SaveAs,
+    Saved { tab: usize, file: File },
+    CloseList,
+    SwitchTab { index: usize },
     Exit,
+    /// The window is gone, quit without asking
+    Destroyed,
     MenuAbout,
+    Compact,
+    SquashHistory,
+    /// Squashes the history before `date` of the list in the tab with id
+    /// `tab`, all of it if `date` is empty
+    Squash { tab: usize, date: String },
     AddRemote,
     PullFromRemote { remote_index: usize},
     PushToRemote { remote_index: usize},
-    PullComplete,
+    PreviewPull { remote_index: usize },
+    PullPreviewed {
+        tab: usize,
+        remote_index: usize,
+        copies: Arc<Vec<Vec<u8>>>,
+        signatures: Arc<Signatures>,
+        preview: Preview,
+    },
+    ScheduleRetry,
+    RetryOutbox,
+    CheckReminders,
+    SnoozeReminder { id: String },
+    SnoozeUntilTomorrow { id: String },
+    /// Shows the item a notification was about
+    ShowItem { id: String },
+    PullFinished {
+        tab: usize,
+        location: String,
+        remote_changes: Option<Arc<HashSet<String>>>,
+        error: Option<String>,
+    },
+    GoLive { remote_index: usize },
+    Live { tab: usize, url: String, event: LiveEvent },
+    LiveListen,
+    DBusListen,
+    DBus { request: dbus::Request },
+    SharePort,
+    ConnectToPeer,
+    RepairProblem { index: usize, object_id: Option<String> },
+    DeleteProblem { index: usize, object_id: Option<String> },
+    Error { message: String },
+    DismissError,
 }
 
 impl Component for Model {
@@ -187,144 +783,253 @@ impl Component for Model {
     type Properties = ();
 
     fn update(&mut self, msg: Self::Message) -> UpdateAction<Self> {
-        let left = self.filter(Filter::Active).iter().count();
+        let action = self.handle(msg);
+        self.announce();
+        action
+    }
+
+    fn view(&self) -> VNode<Model> {
+        self.render()
+    }
+}
+
+impl Model {
+    fn handle(&mut self, msg: Msg) -> UpdateAction<Self> {
+        let left = self.tab().count(Filter::Active);
         match msg {
             Msg::NoOp => return UpdateAction::None,
             Msg::Add { item } => {
-                let new_item = Item::new(item.clone());
-                let mut frontend = self.frontend.lock().unwrap();
-                let change = frontend.change::<_, automerge::InvalidChangeRequest>(Some("Add item".to_string()), |doc| {
-                    let todos = doc.value_at_path(&automerge::Path::root().key("todos")).unwrap();
-                    let num_todos = match todos {
-                        automerge::Value::Sequence(elems) => elems.len(),
-                        _ => panic!()
-                    };
-                    doc.add_change(automerge::LocalChange::insert(
-                        automerge::Path::root().key("todos").index(num_todos.try_into().unwrap()),
-                        hashmap!{
-                            "value" => automerge::Value::Primitive(new_item.task.as_str().into()),
-                            "completed" => automerge::Value::Primitive(new_item.done.into()),
-                            "id" => automerge::Value::Primitive(new_item.id.as_str().into()),
-                        }.into()
-                    ))?;
-                    Ok(())
-                }).unwrap();
-                if let Some(change) = change {
-                    let mut backend = self.backend.lock().unwrap();
-                    let patch = backend.apply_local_change(change).unwrap().0;
-                    frontend.apply_patch(patch).unwrap();
-                }
-                self.clean = false;
-            }
-            Msg::Remove { index } => {
-                //Arc::make_mut(&mut self.items).remove(index);
-                let mut frontend = self.frontend.lock().unwrap();
-                let change = frontend.change::<_, automerge::InvalidChangeRequest>(None, |doc| {
-                    doc.add_change(
-                        automerge::LocalChange::delete(automerge::Path::root().key("todos").index(index.try_into().unwrap()))
-                    )?;
-                    Ok(())
-                }).unwrap();
-                let mut backend = self.backend.lock().unwrap();
-                if let Some(change) = change {
-                    let patch = backend.apply_local_change(change).unwrap().0;
-                    frontend.apply_patch(patch).unwrap();
-                }
-                self.clean = false;
-            }
-            Msg::Toggle { index } => {
-                let mut frontend = self.frontend.lock().unwrap();
-                let change = frontend.change::<_, automerge::InvalidChangeRequest>(None, |doc| {
-                    let index: u32 = index.try_into().unwrap();
-                    let existing = doc.value_at_path(&automerge::Path::root().key("todos").index(index).key("completed"));
-                    let current = match existing {
-                        Some(automerge::Value::Primitive(automerge::ScalarValue::Boolean(b))) => b,
-                        _ => panic!()
-                    };
-                    doc.add_change(automerge::LocalChange::set(
-                        automerge::Path::root().key("todos").index(index).key("completed"),
-                        automerge::ScalarValue::Boolean(!current).into(),
-                    ))?;
-                    Ok(())
-                }).unwrap();
-                if let Some(change) = change {
-                    let mut backend = self.backend.lock().unwrap();
-                    let patch = backend.apply_local_change(change).unwrap().0;
-                    frontend.apply_patch(patch).unwrap();
-                }
-                self.clean = false;
+                self.add(item);
             }
-            Msg::Filter { filter } => {
-                self.filter = filter;
+            Msg::Remove { id } => {
+                self.tab().document.lock().unwrap().remove(&id);
+                self.tab_mut().clean = false;
             }
-            Msg::ToggleAll if left > 0 => {
-                let filtered_ids: Vec<String> = self.filter(self.filter).iter().map(|i| i.id.clone()).collect();
-
-                let mut frontend = self.frontend.lock().unwrap();
-                let change = frontend.change::<_, automerge::InvalidChangeRequest>(None, |doc| {
-                    let todos = doc.value_at_path(&automerge::Path::root().key("todos"));
-                    let todos_count: u32 = match todos {
-                        Some(automerge::Value::Sequence(elems)) => elems.len() as u32,
-                        _ => panic!()
-                    };
-                    for i in 0..todos_count {
-                        let path = automerge::Path::root().key("todos").index(i);
-                        let current_value = match doc.value_at_path(&path) {
-                            Some(automerge::Value::Primitive(automerge::ScalarValue::Boolean(b))) => b,
-                            _ => panic!()
-                        };
-                        let id_path = automerge::Path::root().key("todos").index(i).key("id");
-                        let current_id = match doc.value_at_path(&id_path) {
-                            Some(automerge::Value::Primitive(automerge::ScalarValue::Str(id))) => id,
-                            _ => panic!(),
-                        };
-                        if filtered_ids.contains(&current_id) {
-                            doc.add_change(automerge::LocalChange::set(path, automerge::Value::Primitive(automerge::ScalarValue::Boolean(!current_value))))?;
-                        }
+            Msg::Restore { id } => {
+                self.tab().document.lock().unwrap().restore(&id);
+                self.tab_mut().clean = false;
+            }
+            Msg::EmptyTrash => {
+                if !confirm_empty_trash(self.tab().count(Filter::Trash)) {
+                    return UpdateAction::None;
+                }
+                self.tab().document.lock().unwrap().empty_trash();
+                self.tab_mut().clean = false;
+            }
+            Msg::Toggle { id } => {
+                self.tab().document.lock().unwrap().toggle(&id);
+                self.tab_mut().clean = false;
+            }
+            Msg::Rename { id, task } => {
+                let tab = self.tab_mut();
+                tab.editing = None;
+                if !task.trim().is_empty() {
+                    tab.document.lock().unwrap().rename(&id, task.trim());
+                    tab.clean = false;
+                }
+            }
+            Msg::SetDue { id, due } => {
+                let due = if due.trim().is_empty() {
+                    Ok(None)
+                } else {
+                    chrono::NaiveDate::parse_from_str(due.trim(), DUE_FORMAT)
+                        .map(Some)
+                        .map_err(|_| format!("\"{}\" isn't a date like 2021-03-04", due.trim()))
+                };
+                match due {
+                    Ok(due) => {
+                        let tab = self.tab_mut();
+                        tab.editing = None;
+                        tab.document.lock().unwrap().set_due(&id, due);
+                        tab.clean = false;
                     }
-                    Ok(())
-                }).unwrap();
-                if let Some(change) = change {
-                    let mut backend = self.backend.lock().unwrap();
-                    let patch = backend.apply_local_change(change).unwrap().0;
-                    frontend.apply_patch(patch).unwrap()
+                    Err(e) => self.error = Some(e),
                 }
-                self.clean = false;
+            }
+            Msg::SetRecurrence { id, rule } => {
+                let recurrence = if rule.trim().is_empty() { Ok(None) } else { rule.parse().map(Some) };
+                match recurrence {
+                    Ok(recurrence) => {
+                        let tab = self.tab_mut();
+                        tab.editing = None;
+                        tab.document.lock().unwrap().set_recurrence(&id, recurrence.as_ref());
+                        tab.clean = false;
+                    }
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            Msg::FocusNewItem => self.focus_new_item = true,
+            Msg::FocusChanged { item } => {
+                self.focus_new_item = false;
+                self.on_item = item.is_some();
+                if item.is_some() {
+                    self.tab_mut().cursor = item;
+                }
+            }
+            Msg::MoveCursor { by } => {
+                let tab = self.tab_mut();
+                let items = tab.shown_items();
+                if items.is_empty() {
+                    return UpdateAction::None;
+                }
+                let last = items.len() as isize - 1;
+                let index = match tab.cursor.as_ref().and_then(|id| items.iter().position(|item| &item.id == id)) {
+                    Some(index) => (index as isize + by).max(0).min(last),
+                    None if by > 0 => 0,
+                    None => last,
+                };
+                tab.cursor = Some(items[index as usize].id.clone());
+                tab.editing = None;
+            }
+            Msg::ToggleCursor => match self.tab().cursor.clone() {
+                Some(id) => return self.update(Msg::Toggle { id }),
+                None => return UpdateAction::None,
+            },
+            Msg::RemoveCursor => {
+                let tab = self.tab_mut();
+                let id = match tab.cursor.clone() {
+                    Some(id) => id,
+                    None => return UpdateAction::None,
+                };
+                // Keep the keyboard in the list, on the item after the one
+                // deleted, or the one before if it was the last
+                let items = tab.shown_items();
+                let index = items.iter().position(|item| item.id == id);
+                tab.cursor = index
+                    .and_then(|index| items.get(index + 1).or_else(|| index.checked_sub(1).and_then(|i| items.get(i))))
+                    .map(|item| item.id.clone());
+                return self.update(Msg::Remove { id });
+            }
+            Msg::EditCursor => {
+                let tab = self.tab_mut();
+                tab.editing = tab.cursor.clone();
+            }
+            Msg::CancelEdit => self.tab_mut().editing = None,
+            Msg::PullAll => {
+                // Each pull is deferred on its own, so go through the "pull"
+                // action once per remote
+                if let Some(app) = gio::Application::get_default() {
+                    for index in 0..self.tab().remotes.len() {
+                        app.activate_action("pull", Some(&(index as u64).to_variant()));
+                    }
+                }
+                return UpdateAction::None;
+            }
+            Msg::PushAll => {
+                let current = self.current;
+                let failed: Vec<String> = (0..self.tab().remotes.len())
+                    .filter_map(|index| {
+                        self.push(current, index).err().map(|e| format!("{}: {}", self.tab().remotes[index].location, e))
+                    })
+                    .collect();
+                if !failed.is_empty() {
+                    self.error = Some(format!("Some pushes failed, they will be retried:\n{}", failed.join("\n")));
+                    schedule_retry();
+                }
+            }
+            Msg::ShowShortcuts => {
+                show_shortcuts(&self.shortcuts);
+                return UpdateAction::None;
+            }
+            Msg::Filter { filter } => {
+                let tab = self.tab_mut();
+                tab.filter = filter;
+                tab.shown_rows = PAGE_SIZE;
+            }
+            Msg::ShowNextPage if self.tab().shown_rows < self.tab().count(self.tab().filter) => {
+                self.tab_mut().shown_rows += PAGE_SIZE;
+            }
+            Msg::ShowNextPage => return UpdateAction::None,
+            Msg::ToggleAll if left > 0 => {
+                let tab = self.tab_mut();
+                let filtered_ids: Vec<String> = tab.filter(tab.filter).iter().map(|i| i.id.clone()).collect();
+                tab.document.lock().unwrap().toggle_all(&filtered_ids);
+                tab.clean = false;
             }
             Msg::ToggleAll => return UpdateAction::None,
             Msg::ClearCompleted => {
-                let mut frontend = self.frontend.lock().unwrap();
-                let change = frontend.change::<_, automerge::InvalidChangeRequest>(None, |doc| {
-                    let todos = doc.value_at_path(&automerge::Path::root().key("todos"));
-                    let mut todos_count: u32 = match todos {
-                        Some(automerge::Value::Sequence(elems)) => elems.len() as u32,
-                        _ => panic!()
+                self.tab().document.lock().unwrap().clear_completed();
+                self.tab_mut().clean = false;
+                return UpdateAction::Render
+            }
+            Msg::NewList => {
+                let document = self.new_document();
+                self.open_tab(document, None)
+            }
+            Msg::Open => {
+                return UpdateAction::defer(async {
+                    match open().await {
+                        Ok(Some((file, items))) => Msg::Opened { file, items },
+                        Ok(None) => Msg::NoOp,
+                        Err(e) => Msg::Error { message: format!("Could not open the list: {}", e) },
+                    }
+                })
+            }
+            Msg::Opened { file, items } => {
+                let mut document = self.new_document();
+                match document.set_items(Some("Open file".to_string()), &items) {
+                    Ok(_) => self.open_tab(document, Some(file)),
+                    Err(e) => {
+                        let _ = document.discard_autosave();
+                        self.error = Some(format!("Could not open the list: {}", e));
+                    }
+                }
+            }
+            Msg::Save | Msg::SaveAs => {
+                let tab = self.tab();
+                let id = tab.id;
+                let items = tab.items();
+                let file = if let Msg::Save = msg { tab.file.clone() } else { None };
+                return UpdateAction::defer(async move {
+                    let saved = match file {
+                        Some(file) => save(&items, &file).await.map(|_| Some(file)),
+                        None => save_as(&items).await,
                     };
-                    let mut i = 0;
-                    while i < todos_count {
-                        let path = automerge::Path::root().key("todos").index(i);
-                        let current_value = match doc.value_at_path(&path.clone().key("completed")) {
-                            Some(automerge::Value::Primitive(automerge::ScalarValue::Boolean(b))) => b,
-                            _ => panic!()
-                        };
-                        if current_value {
-                            todos_count -= 1;
-                            doc.add_change(automerge::LocalChange::delete(path))?;
-                        } else {
-                            i += 1;
-                        }
+                    match saved {
+                        Ok(Some(file)) => Msg::Saved { tab: id, file },
+                        Ok(None) => Msg::NoOp,
+                        Err(e) => Msg::Error { message: format!("Could not save the list: {}", e) },
                     }
-                    Ok(())
-                }).unwrap();
-                if let Some(change) = change {
-                    let mut backend = self.backend.lock().unwrap();
-                    let patch = backend.apply_local_change(change).unwrap().0;
-                    frontend.apply_patch(patch).unwrap()
-                }
-                self.clean = false;
-                return UpdateAction::Render
+                });
             }
+            Msg::Saved { tab, file } => {
+                if let Some(index) = self.tab_index(tab) {
+                    let tab = &mut self.tabs[index];
+                    tab.file = Some(file);
+                    tab.clean = true;
+                }
+            }
+            Msg::CloseList => {
+                if self.tabs.len() == 1 {
+                    return UpdateAction::None;
+                }
+                if self.tab().lost_on_close() && !confirm_discard(&[self.tab().title()]) {
+                    return UpdateAction::None;
+                }
+                self.tab().closed.store(true, Ordering::SeqCst);
+                if let Err(e) = self.tab().document.lock().unwrap().discard_autosave() {
+                    self.error = Some(format!("Could not delete the autosave: {}", e));
+                }
+                self.tabs.remove(self.current);
+                self.current = self.current.min(self.tabs.len() - 1);
+            }
+            Msg::SwitchTab { index } if index != self.current && index < self.tabs.len() => {
+                self.current = index;
+                self.on_item = false;
+            }
+            Msg::SwitchTab { .. } => return UpdateAction::None,
             Msg::Exit => {
+                let unsaved: Vec<String> = self.tabs.iter().filter(|tab| tab.unsaved()).map(Tab::title).collect();
+                if !unsaved.is_empty() && !confirm_discard(&unsaved) {
+                    return UpdateAction::None;
+                }
+                return self.update(Msg::Destroyed);
+            }
+            Msg::Destroyed => {
+                // Start faster next time by not having to replay the journal
+                for tab in &self.tabs {
+                    let _ = tab.document.lock().unwrap().compact();
+                }
                 vgtk::quit();
                 return UpdateAction::None;
             }
@@ -332,80 +1037,465 @@ impl Component for Model {
                 AboutDialog::run();
                 return UpdateAction::None;
             }
+            Msg::Compact => {
+                if let Err(e) = self.tab().document.lock().unwrap().compact() {
+                    self.error = Some(e);
+                }
+            }
+            Msg::SquashHistory => {
+                let tab = self.tab().id;
+                return UpdateAction::defer(async move {
+                    match ask_squash().await {
+                        Some(date) => Msg::Squash { tab, date },
+                        None => Msg::NoOp,
+                    }
+                });
+            }
+            Msg::Squash { tab, date } => {
+                let index = match self.tab_index(tab) {
+                    Some(index) => index,
+                    None => return UpdateAction::None,
+                };
+                let squashed = if date.trim().is_empty() {
+                    Ok(None)
+                } else {
+                    compact::parse_date(&date).map(Some)
+                }
+                .and_then(|before| {
+                    let mut document = self.tabs[index].document.lock().unwrap();
+                    let squashed = compact::squash(&document.save()?, before, self.signer.clone())?;
+                    document.replace_history(squashed)
+                });
+                match squashed {
+                    Ok(()) => self.tabs[index].clean = false,
+                    Err(e) => self.error = Some(e),
+                }
+            }
             Msg::AddRemote => {
-                let url = self.new_remote_buffer.get_text();
-                if url.len() > 0 {
-                    self.new_remote_buffer.set_text("");
-                    self.remotes.push(Remote{url});
-                }
-            }
-            Msg::PullFromRemote { remote_index} => {
-                let remote = self.remotes.get(remote_index).unwrap();
-                let url = remote.url.clone();
-                let frontend = self.frontend.clone();
-                let backend = self.backend.clone();
-                let url: reqwest::Url = reqwest::Url::from_str(url.as_str()).unwrap();
+                let location = self.new_remote_buffer.get_text();
+                if location.len() > 0 {
+                    // A credentials file we can't use shouldn't stop remotes
+                    // which don't need it from being added, so add the remote
+                    // without credentials and say why
+                    let credentials = match config::load_credentials() {
+                        Ok(mut all) => all.remove(location.as_str()),
+                        Err(e) => {
+                            if location.starts_with("http://") || location.starts_with("https://") {
+                                self.error = Some(format!("Added {} without credentials: {}", location, e));
+                            }
+                            None
+                        }
+                    };
+                    let passphrase = Some(self.new_passphrase_buffer.get_text().to_string()).filter(|p| !p.is_empty());
+                    match Remote::new(location.to_string(), credentials, passphrase) {
+                        Ok(remote) => {
+                            self.new_remote_buffer.set_text("");
+                            self.new_passphrase_buffer.set_text("");
+                            self.tab_mut().remotes.push(remote);
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            }
+            Msg::PullFromRemote { remote_index } => {
+                let tab = self.tab();
+                let id = tab.id;
+                let remote = match tab.remotes.get(remote_index) {
+                    Some(remote) => remote.clone(),
+                    None => return UpdateAction::None,
+                };
+                let document = tab.document.clone();
                 return UpdateAction::defer(async move {
-                    let bytes = reqwest::blocking::get(url).and_then(|r| r.bytes()).unwrap();
-                    let changes: Vec<automerge::Change> = automerge::Change::load_document(&bytes).unwrap();
-                    let mut backend = backend.lock().unwrap();
-                    let patch = backend.apply_changes(changes).unwrap();
-                    let mut frontend = frontend.lock().unwrap();
-                    frontend.apply_patch(patch).unwrap();
-                    Msg::PullComplete
+                    let result = remote.fetch().and_then(|copies| {
+                        let signatures = remote.fetch_signatures()?;
+                        let flagged = merge_pulled(&mut document.lock().unwrap(), &copies, &signatures)?;
+                        Ok((flagged, remote_changes(&copies)))
+                    });
+                    pulled(id, &remote.location, result)
                 })
             }
-            Msg::PushToRemote{ remote_index } => {
-                let remote = self.remotes.get(remote_index).unwrap();
-                let backend = self.backend.lock().unwrap();
-                let raw = backend.save().unwrap();
-                let client = reqwest::blocking::Client::new();
-                let url = reqwest::Url::from_str(remote.url.as_str()).unwrap();
-                client.post(url).body(raw).send().unwrap();
+            Msg::PreviewPull { remote_index } => {
+                let tab = self.tab();
+                let id = tab.id;
+                let remote = match tab.remotes.get(remote_index) {
+                    Some(remote) => remote.clone(),
+                    None => return UpdateAction::None,
+                };
+                let document = tab.document.clone();
+                return UpdateAction::defer(async move {
+                    let result = remote.fetch().and_then(|copies| {
+                        let signatures = remote.fetch_signatures()?;
+                        let preview = preview::preview(&document.lock().unwrap(), &copies, &signatures, &load_keyring()?)?;
+                        Ok((copies, signatures, preview))
+                    });
+                    match result {
+                        Ok((copies, signatures, preview)) => Msg::PullPreviewed {
+                            tab: id,
+                            remote_index,
+                            copies: Arc::new(copies),
+                            signatures: Arc::new(signatures),
+                            preview,
+                        },
+                        Err(e) => Msg::Error { message: format!("Pull from {} failed: {}", remote.location, e) },
+                    }
+                })
+            }
+            Msg::PullPreviewed { tab, remote_index, copies, signatures, preview } => {
+                // The tab may have been closed while we were fetching
+                let found = self.tab_index(tab).and_then(|index| {
+                    let tab = &self.tabs[index];
+                    tab.remotes.get(remote_index).map(|remote| (tab.document.clone(), remote.location.clone()))
+                });
+                let (document, location) = match found {
+                    Some(found) => found,
+                    None => return UpdateAction::None,
+                };
+                if !confirm_pull(&location, &preview) {
+                    return UpdateAction::None;
+                }
+                // Merge what was previewed rather than pulling again, the
+                // remote may have changed since
+                let result = merge_pulled(&mut document.lock().unwrap(), &copies, &signatures)
+                    .map(|flagged| (flagged, remote_changes(&copies)));
+                return self.update(pulled(tab, &location, result));
+            }
+            Msg::PushToRemote { remote_index } => {
+                if let Err(e) = self.push(self.current, remote_index) {
+                    let location = &self.tab().remotes[remote_index].location;
+                    self.error = Some(format!("Push to {} failed, it will be retried: {}", location, e));
+                    schedule_retry();
+                }
+            }
+            Msg::ScheduleRetry if !self.retry_scheduled && !self.outbox.lock().unwrap().is_empty() => {
+                self.retry_scheduled = true;
+                return UpdateAction::defer(async {
+                    glib::timeout_future_seconds(RETRY_TICK).await;
+                    Msg::RetryOutbox
+                });
+            }
+            Msg::ScheduleRetry => return UpdateAction::None,
+            Msg::CheckReminders => {
+                let lists: Vec<Arc<Items>> = self.tabs.iter().map(Tab::items).collect();
+                let changes = self.reminders.lock().unwrap().check(lists.iter().flat_map(|items| items.iter()));
+                notify(&changes);
+                return UpdateAction::defer(async {
+                    glib::timeout_future_seconds(REMINDER_TICK).await;
+                    Msg::CheckReminders
+                });
+            }
+            Msg::SnoozeReminder { id } => {
+                self.reminders.lock().unwrap().snooze(&id, chrono::Duration::hours(1));
+                return UpdateAction::None;
+            }
+            Msg::SnoozeUntilTomorrow { id } => {
+                self.reminders.lock().unwrap().snooze_until_tomorrow(&id);
+                return UpdateAction::None;
+            }
+            Msg::ShowItem { id } => {
+                let found = self.tabs.iter().position(|tab| tab.items().iter().any(|item| item.id == id));
+                match found {
+                    Some(index) => {
+                        self.current = index;
+                        let tab = self.tab_mut();
+                        tab.filter = Filter::All;
+                        tab.cursor = Some(id);
+                    }
+                    None => return UpdateAction::None,
+                }
+            }
+            Msg::RetryOutbox => {
+                self.retry_scheduled = false;
+                let due = self.outbox.lock().unwrap().due(chrono::Utc::now().timestamp());
+                for location in due {
+                    // The failure is recorded in the outbox and shown on the
+                    // remote's row. Pushes of lists which aren't open wait.
+                    let pending = self.outbox.lock().unwrap().get(&location).cloned();
+                    if let Some((tab, index)) = pending.and_then(|pending| self.tab_for_push(&location, &pending)) {
+                        let _ = self.push(tab, index);
+                    }
+                }
+                schedule_retry();
+            }
+            Msg::PullFinished { tab, location, remote_changes, error } => {
+                if let Some(index) = self.tab_index(tab) {
+                    let status = self.tabs[index].sync_status.entry(location).or_default();
+                    status.last_pull = Some(Attempt::now(error.clone()));
+                    if remote_changes.is_some() {
+                        status.remote_changes = remote_changes;
+                    }
+                }
+                if error.is_some() {
+                    self.error = error;
+                }
+            }
+            Msg::GoLive { remote_index } => {
+                if let Err(e) = allow_unsigned() {
+                    self.error = Some(e);
+                    return UpdateAction::Render;
+                }
+                let tab = self.tab();
+                let remote = &tab.remotes[remote_index];
+                let location = &remote.location;
+                if remote.is_encrypted() {
+                    self.error = Some(format!("{} is encrypted, live sync would send changes in the clear", location));
+                    return UpdateAction::Render;
+                }
+                let url = match live::live_url(location) {
+                    Some(url) => url,
+                    None => {
+                        self.error = Some(format!("Live sync needs an http:// remote, not {}", location));
+                        return UpdateAction::Render;
+                    }
+                };
+                if self.tabs.iter().any(|tab| tab.live_status.contains_key(&url)) {
+                    return UpdateAction::None;
+                }
+                let mut document = tab.document.lock().unwrap();
+                let local = document.subscribe_local_changes();
+                live::connect(url.clone(), remote.authorization(), document.all_changes(), local, self.live_events());
+                drop(document);
+                self.tab_mut().live_status.insert(url, "connecting".to_string());
+                return self.start_listening();
+            }
+            Msg::Live { tab, url, event } => {
+                self.listening = false;
+                // Updates can't both render and wait for the next event, so
+                // have the "live" action wait for it
+                if let Some(app) = gio::Application::get_default() {
+                    app.activate_action("live", None);
+                }
+                // Events for a tab which has been closed
+                let index = match self.tab_index(tab) {
+                    Some(index) => index,
+                    None => return UpdateAction::None,
+                };
+                let tab = &mut self.tabs[index];
+                if !tab.live_status.contains_key(&url) {
+                    // Someone connected to us
+                    tab.peers.push(url.clone());
+                }
+                match event {
+                    LiveEvent::Connected => {
+                        tab.live_status.insert(url, "live".to_string());
+                    }
+                    LiveEvent::Disconnected(reason) => {
+                        tab.live_status.insert(url, format!("disconnected: {}", reason));
+                    }
+                    LiveEvent::Refused(reason) => {
+                        tab.live_status.insert(url, format!("refused: {}", reason));
+                    }
+                    LiveEvent::Change(bytes) => {
+                        // Changes come without signatures, so they're
+                        // checked like unsigned changes in a pull
+                        let result = load_keyring().and_then(|keyring| {
+                            let change = document::decode_change(bytes)?;
+                            let mut document = tab.document.lock().unwrap();
+                            document.apply_verified(vec![change], &Signatures::new(), &keyring)
+                        });
+                        match result {
+                            Ok(flagged) if flagged.iter().any(|flag| flag.rejected) => {
+                                self.error = Some(describe_flagged(&url, &flagged));
+                            }
+                            Ok(flagged) if !flagged.is_empty() => {
+                                tab.live_status.insert(url, "live, receiving unsigned changes".to_string());
+                            }
+                            Ok(_) => {
+                                tab.live_status.entry(url).or_insert_with(|| "live".to_string());
+                            }
+                            Err(e) => self.error = Some(format!("Sync with {} failed: {}", url, e)),
+                        }
+                    }
+                }
+            }
+            Msg::LiveListen => return self.start_listening(),
+            Msg::DBusListen => {
+                let receiver = self.dbus_receiver.clone();
+                return UpdateAction::defer(async move {
+                    match receiver.lock().await.next().await {
+                        Some(request) => Msg::DBus { request },
+                        None => Msg::NoOp,
+                    }
+                });
+            }
+            Msg::DBus { request } => {
+                // As with live events, have the "dbus" action wait for the
+                // next call so this one can render
+                if let Some(app) = gio::Application::get_default() {
+                    app.activate_action("dbus", None);
+                }
+                let result = self.answer(request.call.clone());
+                request.reply(result);
+            }
+            Msg::SharePort => {
+                if let Err(e) = allow_unsigned() {
+                    self.error = Some(e);
+                    return UpdateAction::Render;
+                }
+                // Peers which connect to us aren't told which list they want
+                if self.tabs.iter().any(|tab| tab.sharing.is_some()) {
+                    self.error = Some("Only one list can be shared at a time".to_string());
+                    return UpdateAction::Render;
+                }
+                let port = self.share_port_buffer.get_text();
+                let shared = port
+                    .trim()
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid port \"{}\"", port))
+                    .and_then(|port| {
+                        let code = p2p::pairing_code();
+                        p2p::share(port, code.clone(), self.tab().document.clone(), self.live_events())
+                            .map(|address| (address, code))
+                            .map_err(|e| format!("Could not share on port {}: {}", port, e))
+                    });
+                match shared {
+                    Ok((address, code)) => {
+                        self.tab_mut().sharing = Some(format!("{} with pairing code {}", address, code));
+                        return self.start_listening();
+                    }
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            Msg::ConnectToPeer => {
+                if let Err(e) = allow_unsigned() {
+                    self.error = Some(e);
+                    return UpdateAction::Render;
+                }
+                let address = self.peer_address_buffer.get_text().trim().to_string();
+                let code = self.peer_code_buffer.get_text().trim().to_string();
+                if address.is_empty() || self.tabs.iter().any(|tab| tab.live_status.contains_key(&address)) {
+                    return UpdateAction::None;
+                }
+                if code.is_empty() {
+                    self.error = Some(format!("Enter the pairing code {} shows when it shares", address));
+                    return UpdateAction::Render;
+                }
+                p2p::connect(address.clone(), code, self.tab().document.clone(), self.live_events());
+                self.peer_address_buffer.set_text("");
+                self.peer_code_buffer.set_text("");
+                let tab = self.tab_mut();
+                tab.peers.push(address.clone());
+                tab.live_status.insert(address, "connecting".to_string());
+                return self.start_listening();
+            }
+            Msg::RepairProblem { index, object_id } => {
+                self.tab().document.lock().unwrap().fix_problem(index, object_id, true);
+                self.tab_mut().clean = false;
+            }
+            Msg::DeleteProblem { index, object_id } => {
+                self.tab().document.lock().unwrap().fix_problem(index, object_id, false);
+                self.tab_mut().clean = false;
+            }
+            Msg::Error { message } => {
+                self.error = Some(message);
+            }
+            Msg::DismissError => {
+                self.error = None;
+                self.tab().document.lock().unwrap().dismiss_autosave_error();
             }
-            Msg::PullComplete => return UpdateAction::Render
         }
         UpdateAction::Render
     }
 
-    fn view(&self) -> VNode<Model> {
-        let title = if let Some(name) = self.file.as_ref().and_then(|p| p.get_basename()) {
-            name.to_str().unwrap().to_string()
-        } else {
-            "Untitled todo list".to_string()
-        };
-        let clean = if self.clean { "" } else { " *" };
+    fn render(&self) -> VNode<Model> {
+        let tab = self.tab();
+        let clean = if tab.unsaved() { " *" } else { "" };
 
         use vgtk::menu;
         let main_menu = menu()
-            .section(menu().item("Open...", "win.open"))
             .section(
                 menu()
-                    .item("Save", "win.save")
-                    .item("Save as...", "win.save-as"),
+                    .item("New list", "app.new-list")
+                    .item("Open...", "app.open"),
+            )
+            .section(
+                menu()
+                    .item("Save", "app.save")
+                    .item("Save as...", "app.save-as")
+                    .item("Close list", "app.close-list"),
+            )
+            .section(
+                menu()
+                    .item("Compact", "app.compact")
+                    .item("Squash history...", "app.squash"),
+            )
+            .section(
+                menu()
+                    .item("Keyboard shortcuts", "app.shortcuts")
+                    .item("About...", "app.about"),
             )
-            .section(menu().item("About...", "app.about"))
             .section(menu().item("Quit", "app.quit"))
             .build();
 
         gtk! {
             <Application::new_unwrap(Some("camp.lol.todomvc"), ApplicationFlags::empty())>
 
-                <SimpleAction::new("quit", None) Application::accels=["<Ctrl>q"].as_ref() enabled=true
-                        on activate=|a, _| Msg::Exit/>
+                <SimpleAction::new("quit", None) enabled=true on activate=|_, _| Msg::Exit/>
                 <SimpleAction::new("about", None) enabled=true on activate=|_, _| Msg::MenuAbout/>
+                <SimpleAction::new("compact", None) enabled=true on activate=|_, _| Msg::Compact/>
+                <SimpleAction::new("squash", None) enabled=true on activate=|_, _| Msg::SquashHistory/>
+                <SimpleAction::new("live", None) enabled=true on activate=|_, _| Msg::LiveListen/>
+                <SimpleAction::new("dbus", None) enabled=true on activate=|_, _| Msg::DBusListen/>
+                <SimpleAction::new("outbox", None) enabled=true on activate=|_, _| Msg::ScheduleRetry/>
+                <SimpleAction::new("reminders", None) enabled=true on activate=|_, _| Msg::CheckReminders/>
+                <SimpleAction::new("snooze", VariantTy::new("s").ok()) enabled=true
+                        on activate=|_, id| match id.and_then(|id| id.get::<String>()) {
+                            Some(id) => Msg::SnoozeReminder { id },
+                            None => Msg::NoOp,
+                        }/>
+                <SimpleAction::new("snooze-tomorrow", VariantTy::new("s").ok()) enabled=true
+                        on activate=|_, id| match id.and_then(|id| id.get::<String>()) {
+                            Some(id) => Msg::SnoozeUntilTomorrow { id },
+                            None => Msg::NoOp,
+                        }/>
+                <SimpleAction::new("show-item", VariantTy::new("s").ok()) enabled=true
+                        on activate=|_, id| match id.and_then(|id| id.get::<String>()) {
+                            Some(id) => Msg::ShowItem { id },
+                            None => Msg::NoOp,
+                        }/>
+                <SimpleAction::new("pull", VariantTy::new("t").ok()) enabled=true
+                        on activate=|_, index| match index.and_then(|index| index.get::<u64>()) {
+                            Some(index) => Msg::PullFromRemote { remote_index: index as usize },
+                            None => Msg::NoOp,
+                        }/>
+                <SimpleAction::new("new-list", None) enabled=true on activate=|_, _| Msg::NewList/>
+                <SimpleAction::new("open", None) enabled=true on activate=|_, _| Msg::Open/>
+                <SimpleAction::new("save", None) enabled=true on activate=|_, _| Msg::Save/>
+                <SimpleAction::new("save-as", None) enabled=true on activate=|_, _| Msg::SaveAs/>
+                <SimpleAction::new("close-list", None) enabled=self.tabs.len() > 1 on activate=|_, _| Msg::CloseList/>
+
+                <SimpleAction::new("new-item", None) enabled=true on activate=|_, _| Msg::FocusNewItem/>
+                <SimpleAction::new("next-item", None) enabled=true on activate=|_, _| Msg::MoveCursor { by: 1 }/>
+                <SimpleAction::new("previous-item", None) enabled=true on activate=|_, _| Msg::MoveCursor { by: -1 }/>
+                // Disabled unless the keyboard is on an item, so their keys
+                // still reach whatever has it otherwise
+                <SimpleAction::new("toggle-item", None) enabled=self.on_item on activate=|_, _| Msg::ToggleCursor/>
+                <SimpleAction::new("delete-item", None) enabled=self.on_item on activate=|_, _| Msg::RemoveCursor/>
+                <SimpleAction::new("edit-item", None) enabled=self.on_item on activate=|_, _| Msg::EditCursor/>
+                <SimpleAction::new("cancel-edit", None) enabled=tab.editing.is_some() on activate=|_, _| Msg::CancelEdit/>
+                <SimpleAction::new("filter-all", None) enabled=true on activate=|_, _| Msg::Filter { filter: Filter::All }/>
+                <SimpleAction::new("filter-active", None) enabled=true on activate=|_, _| Msg::Filter { filter: Filter::Active }/>
+                <SimpleAction::new("filter-completed", None) enabled=true
+                        on activate=|_, _| Msg::Filter { filter: Filter::Completed }/>
+                <SimpleAction::new("filter-trash", None) enabled=true on activate=|_, _| Msg::Filter { filter: Filter::Trash }/>
+                <SimpleAction::new("pull-all", None) enabled=true on activate=|_, _| Msg::PullAll/>
+                <SimpleAction::new("push-all", None) enabled=true on activate=|_, _| Msg::PushAll/>
+                <SimpleAction::new("shortcuts", None) enabled=true on activate=|_, _| Msg::ShowShortcuts/>
 
-                <ApplicationWindow default_width=1200 default_height=480 border_width=20 on destroy=|_| Msg::Exit>
+                <ApplicationWindow default_width=1200 default_height=480 border_width=10 on destroy=|_| Msg::Destroyed
+                                   on set_focus=|_, widget| Msg::FocusChanged { item: widget.and_then(focused_item) }>
 
-                    <HeaderBar title=format!("TodoMVC - {}{}", title, clean) subtitle="wtf do we do now" show_close_button=true>
+                    <HeaderBar title=format!("TodoMVC - {}{}", tab.title(), clean) subtitle="wtf do we do now" show_close_button=true>
                         <MenuButton HeaderBar::pack_type=PackType::End @MenuButtonExt::direction=ArrowType::Down relief=ReliefStyle::None
                                     image="open-menu-symbolic">
                             <Menu::from_model(&main_menu)/>
                         </MenuButton>
                     </HeaderBar>
-                    {
-                        self.main_panel()
-                    }
+                    <Notebook scrollable=true show_tabs=self.tabs.len() > 1 property_page=self.current as i32
+                              on switch_page=|_, _, page| Msg::SwitchTab { index: page as usize }>
+                        {
+                            self.tabs.iter().enumerate().map(|(index, tab)| self.tab_panel(tab, index))
+                        }
+                    </Notebook>
                 </ApplicationWindow>
             </Application>
         }
@@ -463,3 +1553,298 @@ async fn save_as(items: &Items) -> Result<Option<File>, Error> {
         Ok(None)
     }
 }
+
+/// Has the outbox checked for pushes to retry in a while. Goes through the
+/// "outbox" action so it can be done from updates which need to render too.
+fn schedule_retry() {
+    if let Some(app) = gio::Application::get_default() {
+        app.activate_action("outbox", None);
+    }
+}
+
+/// Raises and withdraws desktop notifications about due tasks. Each is
+/// sent under its item's id, so a reminder that a task is overdue replaces
+/// the one that it was due.
+fn notify(changes: &Changes) {
+    let app = match gio::Application::get_default() {
+        Some(app) => app,
+        None => return,
+    };
+    for id in &changes.withdraw {
+        app.withdraw_notification(id);
+    }
+    for reminder in &changes.raise {
+        let notification = gio::Notification::new(&reminder.title());
+        notification.set_body(Some(&format!("Due {}", reminder.due.format("%A %-d %B"))));
+        if reminder.stage == Stage::Overdue {
+            notification.set_priority(gio::NotificationPriority::High);
+        }
+        let id = reminder.id.to_variant();
+        notification.set_default_action_and_target_value("app.show-item", Some(&id));
+        notification.add_button_with_target_value("Snooze for an hour", "app.snooze", Some(&id));
+        notification.add_button_with_target_value("Snooze until tomorrow", "app.snooze-tomorrow", Some(&id));
+        app.send_notification(Some(&reminder.id), &notification);
+    }
+}
+
+/// Checks every key in `bindings` is one GTK understands
+fn check_accels(bindings: &Bindings) -> Result<(), String> {
+    let invalid: Vec<&str> = SHORTCUTS
+        .iter()
+        .flat_map(|shortcut| bindings.accels(shortcut.action))
+        .filter(|accel| accelerator_parse(accel).0 == 0)
+        .map(String::as_str)
+        .collect();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid keys {}", invalid.join(", ")))
+    }
+}
+
+/// Binds the keys for every action. Done once the application is running,
+/// since there's nowhere to bind them before that.
+fn install_accels(bindings: &Bindings) {
+    let app = match gio::Application::get_default().and_then(|app| app.downcast::<Application>().ok()) {
+        Some(app) => app,
+        None => return,
+    };
+    for shortcut in SHORTCUTS {
+        let accels: Vec<&str> = bindings.accels(shortcut.action).iter().map(String::as_str).collect();
+        app.set_accels_for_action(&format!("app.{}", shortcut.action), &accels);
+    }
+}
+
+/// Has closing the window go through "quit", so it can ask about unsaved
+/// lists first. Done once the application is running, since there's no
+/// window before that.
+fn guard_close() {
+    let app = match gio::Application::get_default().and_then(|app| app.downcast::<Application>().ok()) {
+        Some(app) => app,
+        None => return,
+    };
+    for window in app.get_windows() {
+        window.connect_delete_event(|_, _| {
+            if let Some(app) = gio::Application::get_default() {
+                app.activate_action("quit", None);
+            }
+            Inhibit(true)
+        });
+    }
+}
+
+/// Asks whether to go ahead and lose the changes to the lists named
+fn confirm_discard(titles: &[String]) -> bool {
+    let dialog = MessageDialog::new(
+        vgtk::current_window().as_ref(),
+        DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::None,
+        "Discard unsaved changes?",
+    );
+    dialog.set_property_secondary_text(Some(&format!("These lists have changes which haven't been saved:\n{}", titles.join("\n"))));
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Discard", ResponseType::Accept);
+    let response = dialog.run();
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// Asks whether to delete the items in the trash for good
+fn confirm_empty_trash(count: usize) -> bool {
+    let dialog = MessageDialog::new(
+        vgtk::current_window().as_ref(),
+        DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::None,
+        &match count {
+            1 => "Delete 1 item for good?".to_string(),
+            count => format!("Delete {} items for good?", count),
+        },
+    );
+    dialog.set_property_secondary_text(Some("They can't be restored afterwards, here or by anyone you sync with."));
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Empty trash", ResponseType::Accept);
+    let response = dialog.run();
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// The id of the item a newly focused widget belongs to, unless it's an
+/// entry, where keys should go to the text
+fn focused_item(widget: &Widget) -> Option<String> {
+    if widget.is::<Entry>() {
+        return None;
+    }
+    let row = if widget.is::<ListBoxRow>() {
+        widget.clone()
+    } else {
+        widget.get_ancestor(ListBoxRow::static_type())?
+    };
+    row.get_widget_name().strip_prefix(ITEM_ROW).map(str::to_string)
+}
+
+/// Lists every action, the keys bound to it and its name
+fn show_shortcuts(bindings: &Bindings) {
+    let dialog = Dialog::with_buttons(
+        Some("Keyboard shortcuts"),
+        vgtk::current_window().as_ref(),
+        DialogFlags::MODAL,
+        &[("Close", ResponseType::Close)],
+    );
+    let grid = Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(24);
+    grid.set_border_width(12);
+    for (row, shortcut) in SHORTCUTS.iter().enumerate() {
+        let keys: Vec<String> = bindings
+            .accels(shortcut.action)
+            .iter()
+            .map(|accel| {
+                let (key, modifiers) = accelerator_parse(accel);
+                accelerator_get_label(key, modifiers).map_or_else(|| accel.clone(), |label| label.to_string())
+            })
+            .collect();
+        let keys = if keys.is_empty() { "(none)".to_string() } else { keys.join(", ") };
+        let description = Label::new(Some(shortcut.description));
+        description.set_xalign(0.0);
+        let keys = Label::new(Some(keys.as_str()));
+        keys.set_xalign(0.0);
+        // For rebinding it in shortcuts.json
+        let action = Label::new(None);
+        action.set_markup(&format!("<small>{}</small>", shortcut.action));
+        action.set_xalign(0.0);
+        grid.attach(&description, 0, row as i32, 1, 1);
+        grid.attach(&keys, 1, row as i32, 1, 1);
+        grid.attach(&action, 2, row as i32, 1, 1);
+    }
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    dialog.run();
+    dialog.close();
+}
+
+fn load_keyring() -> Result<Keyring, String> {
+    Keyring::path().map_or(Ok(Keyring::default()), |path| Keyring::load(&path))
+}
+
+/// Live sync and peers send changes without their signatures, so every one
+/// of them would be rejected if the keyring only accepts trusted ones
+fn allow_unsigned() -> Result<(), String> {
+    if load_keyring()?.reject_unknown {
+        return Err("Live sync and peers can't send signatures, and your keyring rejects unsigned changes".to_string());
+    }
+    Ok(())
+}
+
+/// Merges copies of the document pulled from a remote, checking their
+/// signatures, and returns the changes which were flagged
+fn merge_pulled(document: &mut Document, copies: &[Vec<u8>], signatures: &Signatures) -> Result<Vec<Flagged>, String> {
+    let keyring = load_keyring()?;
+    let mut flagged = Vec::new();
+    for data in copies {
+        flagged.extend(document.merge_verified(data, signatures, &keyring)?);
+    }
+    Ok(flagged)
+}
+
+/// Every change in the copies of the document fetched from a remote, if
+/// they could all be read
+fn remote_changes(copies: &[Vec<u8>]) -> Option<Arc<HashSet<String>>> {
+    let mut all = HashSet::new();
+    for data in copies {
+        all.extend(status::change_hashes(data).ok()?);
+    }
+    Some(Arc::new(all))
+}
+
+/// The message to finish a pull from `location` with
+fn pulled(tab: usize, location: &str, result: Result<(Vec<Flagged>, Option<Arc<HashSet<String>>>), String>) -> Msg {
+    let (remote_changes, error) = match result {
+        Ok((flagged, remote_changes)) if flagged.is_empty() => (remote_changes, None),
+        Ok((flagged, remote_changes)) => (remote_changes, Some(describe_flagged(location, &flagged))),
+        Err(e) => (None, Some(format!("Pull from {} failed: {}", location, e))),
+    };
+    Msg::PullFinished {
+        tab,
+        location: location.to_string(),
+        remote_changes,
+        error,
+    }
+}
+
+/// Most changes to list in the pull preview
+const PREVIEW_LINES: usize = 40;
+
+/// Shows what pulling from `location` would change and asks whether to
+/// merge it
+fn confirm_pull(location: &str, preview: &Preview) -> bool {
+    let dialog = MessageDialog::new(
+        vgtk::current_window().as_ref(),
+        DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::None,
+        &format!("Merge changes from {}?", location),
+    );
+    let mut lines: Vec<String> = preview.changes.iter().take(PREVIEW_LINES).map(|change| change.to_string()).collect();
+    if preview.changes.is_empty() {
+        lines.push("Nothing would change.".to_string());
+    } else if preview.changes.len() > PREVIEW_LINES {
+        lines.push(format!("…and {} more", preview.changes.len() - PREVIEW_LINES));
+    }
+    if !preview.flagged.is_empty() {
+        lines.push(String::new());
+        lines.push(describe_flagged(location, &preview.flagged));
+    }
+    dialog.set_property_secondary_text(Some(lines.join("\n").as_str()));
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Merge", ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Cancel);
+    let response = dialog.run();
+    dialog.close();
+    response == ResponseType::Accept
+}
+
+/// Sums up the changes pulled from `location` which weren't signed by
+/// anyone we trust
+fn describe_flagged(location: &str, flagged: &[Flagged]) -> String {
+    let rejected = flagged.iter().filter(|f| f.rejected).count();
+    let mut message = format!(
+        "{}: {} changes rejected, {} without a trusted signature",
+        location,
+        rejected,
+        flagged.len() - rejected
+    );
+    for flag in flagged.iter().take(5) {
+        message.push_str(&format!("\n{}: {}", &flag.hash[..12], flag.reason));
+    }
+    message
+}
+
+/// Warns about squashing and asks which history to squash. Returns the
+/// date entered, empty for all of it, or `None` if the user cancelled.
+async fn ask_squash() -> Option<String> {
+    let dialog = MessageDialog::new(
+        vgtk::current_window().as_ref(),
+        DialogFlags::MODAL,
+        MessageType::Warning,
+        ButtonsType::OkCancel,
+        "Squash history?",
+    );
+    dialog.set_property_secondary_text(Some(SQUASH_WARNING));
+    let entry = Entry::new();
+    entry.set_placeholder_text(Some("Only history before (YYYY-MM-DD), empty for all"));
+    if let Some(area) = dialog.get_message_area().and_then(|w| w.downcast::<Box>().ok()) {
+        area.pack_start(&entry, false, false, 0);
+        entry.show();
+    }
+    dialog.show();
+    let response = on_signal!(dialog, connect_response).await;
+    let date = entry.get_text().to_string();
+    dialog.close();
+    if response == Ok(ResponseType::Ok) {
+        Some(date)
+    } else {
+        None
+    }
+}
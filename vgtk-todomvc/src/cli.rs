@@ -0,0 +1,133 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+use std::sync::Arc;
+
+use todomvc::compact::{self, SQUASH_WARNING};
+use todomvc::config::{self, Credentials};
+use todomvc::signing::{self, Signer};
+
+const USAGE: &str = "usage: todomvc compact <file> [--output <file>] [--squash | --squash-before <YYYY-MM-DD>]";
+const LOGIN_USAGE: &str = "usage: todomvc login <remote> (--token <token> | --user <name> [--password])";
+const LOGOUT_USAGE: &str = "usage: todomvc logout <remote>";
+
+/// Handles `todomvc compact ...`, `todomvc login ...`, `todomvc logout
+/// ...` and `todomvc key`. Returns `None` if the arguments aren't a command line command, so
+/// the GUI should start.
+pub fn run(args: &[String]) -> Option<i32> {
+    let result = match args.get(1).map(String::as_str) {
+        Some("compact") => compact_command(&args[2..]),
+        Some("login") => login_command(&args[2..]),
+        Some("logout") => match &args[2..] {
+            [remote] => config::set_credentials(remote, None),
+            _ => Err(LOGOUT_USAGE.to_string()),
+        },
+        Some("key") => Signer::load_or_create().map(|signer| println!("{}", signer.public_key())),
+        _ => return None,
+    };
+    Some(match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    })
+}
+
+fn login_command(args: &[String]) -> Result<(), String> {
+    let mut remote = None;
+    let mut token = None;
+    let mut username = None;
+    let mut password = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--token" => token = Some(args.next().ok_or(LOGIN_USAGE)?.clone()),
+            "--user" => username = Some(args.next().ok_or(LOGIN_USAGE)?.clone()),
+            "--password" => password = true,
+            _ if remote.is_none() => remote = Some(arg.clone()),
+            _ => return Err(LOGIN_USAGE.to_string()),
+        }
+    }
+    let remote = remote.ok_or(LOGIN_USAGE)?;
+    let credentials = match (token, username) {
+        (Some(token), None) if !password => Credentials::Bearer { token },
+        (None, Some(username)) => Credentials::Basic {
+            username,
+            password: if password { Some(read_password()?) } else { None },
+        },
+        _ => return Err(LOGIN_USAGE.to_string()),
+    };
+    config::set_credentials(&remote, Some(credentials))
+}
+
+/// Reads a password from standard input rather than the command line, where
+/// other users could see it in the process list. On a terminal we ask for it
+/// and turn off echoing while it's typed; otherwise it's the first line piped
+/// in.
+fn read_password() -> Result<String, String> {
+    // stty fails if standard input isn't a terminal, which is fine
+    let stty = |arg: &str| Command::new("stty").arg(arg).status().map_or(false, |status| status.success());
+    let hidden = stty("-echo");
+    if hidden {
+        eprint!("Password: ");
+    }
+    let mut password = String::new();
+    let read = io::stdin().lock().read_line(&mut password);
+    if hidden {
+        stty("echo");
+        eprintln!();
+    }
+    read.map_err(|e| format!("Could not read the password: {}", e))?;
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn compact_command(args: &[String]) -> Result<(), String> {
+    let mut input = None;
+    let mut output = None;
+    let mut squash = false;
+    let mut before = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" => output = Some(args.next().ok_or(USAGE)?.clone()),
+            "--squash" => squash = true,
+            "--squash-before" => {
+                squash = true;
+                before = Some(compact::parse_date(args.next().ok_or(USAGE)?)?);
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let input = input.ok_or(USAGE)?;
+    let output = output.unwrap_or_else(|| input.clone());
+    let data = fs::read(&input).map_err(|e| format!("{}: {}", input, e))?;
+    let compacted = if squash {
+        eprintln!("warning: {}", SQUASH_WARNING);
+        let signer = Arc::new(Signer::load_or_create()?);
+        let squashed = compact::squash(&data, before, Some(signer))?;
+        // The new changes' signatures go in `<output>.sig`, where a file
+        // remote keeps a document's signatures
+        let signatures = format!("{}.sig", output);
+        write_atomically(&signatures, &signing::encode_signatures(squashed.signatures()))
+            .map_err(|e| format!("{}: {}", signatures, e))?;
+        squashed.save()?
+    } else {
+        compact::compact(&data)?
+    };
+    write_atomically(&output, &compacted).map_err(|e| format!("{}: {}", output, e))?;
+    println!("{}: {} bytes -> {} bytes", output, data.len(), compacted.len());
+    Ok(())
+}
+
+/// Writes `data` beside `path` and renames it over it, so a crash leaves
+/// either the old file or the new one, never half of one. By default the
+/// output is the input, which may be the only copy.
+fn write_atomically(path: &str, data: &[u8]) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
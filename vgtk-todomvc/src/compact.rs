@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+
+use crate::document::Document;
+use crate::signing::Signer;
+
+/// Shown before squashing, wherever that's done from
+pub const SQUASH_WARNING: &str = "Squashing rewrites the history of this todo list. \
+Anyone else holding a copy of it, and every remote it was pushed to, still has the old history: \
+merging the two would duplicate every item. After squashing, push the result to your remotes \
+and have everyone else start from an empty list and pull it, rather than pulling into their existing copy.";
+
+/// Re-saves a document in automerge's compressed columnar format, keeping
+/// all of its history
+pub fn compact(data: &[u8]) -> Result<Vec<u8>, String> {
+    let backend = automerge::Backend::load(data.to_vec()).map_err(|e| format!("{:?}", e))?;
+    backend.save().map_err(|e| format!("{:?}", e))
+}
+
+/// Parses a `YYYY-MM-DD` date into the start of that day (UTC) in seconds,
+/// the unit changes record their time in
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .map(|date| date.and_hms(0, 0, 0).timestamp())
+        .map_err(|e| format!("Invalid date \"{}\", expected YYYY-MM-DD: {}", date, e))
+}
+
+/// Builds a new document holding the same todos as `data` with its history
+/// squashed.
+///
+/// Without `before` the whole history becomes a single change. With it, the
+/// changes made before that time become a single base change, and everything
+/// since becomes one more change on top of it: automerge changes refer to the
+/// operations they build on, so the newer changes can't be kept as they were
+/// once their base is rewritten. Malformed entries are dropped either way.
+///
+/// The new changes are signed by `signer`, if there is one, so peers which
+/// only accept signed changes still take them, and the result is stamped
+/// with the current schema version.
+pub fn squash(data: &[u8], before: Option<i64>, signer: Option<Arc<Signer>>) -> Result<Document, String> {
+    let current = Document::load(data.to_vec())?;
+    let mut squashed = Document::default();
+    if let Some(signer) = signer {
+        squashed.set_signer(signer);
+    }
+    match before {
+        Some(before) => {
+            let changes = automerge::Change::load_document(data).map_err(|e| format!("{:?}", e))?;
+            // Changes are in causal order, so a change's dependencies are
+            // always seen before it
+            let mut included = HashSet::new();
+            let old = changes
+                .into_iter()
+                .filter(|change| {
+                    let keep = change.time < before && change.deps.iter().all(|dep| included.contains(dep));
+                    if keep {
+                        included.insert(change.hash);
+                    }
+                    keep
+                })
+                .collect();
+            let base = Document::from_changes(old)?;
+            squashed.set_items(Some("Squashed history".to_string()), &base.items())?;
+            squashed.set_items(Some("Changes since squashed history".to_string()), &current.items())?;
+        }
+        None => {
+            squashed.set_items(Some("Squashed history".to_string()), &current.items())?;
+        }
+    }
+    squashed.migrate()?;
+    Ok(squashed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::items::Item;
+    use crate::schema::{self, SCHEMA_VERSION};
+    use crate::signing::{self, Keyring};
+
+    type Summary = Vec<(String, String, bool, Option<i64>)>;
+
+    fn summary(document: &Document) -> Summary {
+        document
+            .items()
+            .iter()
+            .map(|item| (item.id.clone(), item.task.clone(), item.done, item.deleted_at))
+            .collect()
+    }
+
+    /// A document with a bit of history: items added, completed, renamed
+    /// and trashed
+    fn with_history() -> Document {
+        let mut document = Document::default();
+        for task in &["milk", "eggs", "bread"] {
+            document.add(&Item::new(*task));
+        }
+        let ids: Vec<String> = document.items().iter().map(|item| item.id.clone()).collect();
+        document.toggle(&ids[0]);
+        document.rename(&ids[1], "free range eggs");
+        document.remove(&ids[2]);
+        document
+    }
+
+    #[test]
+    fn compact_keeps_all_of_the_history() {
+        let document = with_history();
+        let compacted = Document::load(compact(&document.save().unwrap()).unwrap()).unwrap();
+        assert_eq!(compacted.change_hashes(), document.change_hashes());
+        assert_eq!(summary(&compacted), summary(&document));
+    }
+
+    #[test]
+    fn squash_keeps_the_todos_and_drops_the_history() {
+        let document = with_history();
+        let data = document.save().unwrap();
+
+        let squashed = squash(&data, None, None).unwrap();
+        assert_eq!(summary(&squashed), summary(&document));
+        // A new document plus one change holding everything, and one
+        // stamping the schema version
+        let empty = Document::default().change_hashes().len();
+        assert_eq!(squashed.change_hashes().len(), empty + 2);
+
+        // Everything was made before tomorrow, and nothing before 1970
+        let tomorrow = chrono::Utc::now().timestamp() + 24 * 60 * 60;
+        for before in vec![tomorrow, 0] {
+            let squashed = squash(&data, Some(before), None).unwrap();
+            assert_eq!(summary(&squashed), summary(&document));
+            assert!(squashed.change_hashes().len() <= empty + 3);
+        }
+    }
+
+    #[test]
+    fn squash_signs_its_changes_and_keeps_the_schema_version() {
+        let signer = Arc::new(Signer::from_secret(&[7; 32]).unwrap());
+        let mut document = with_history();
+        document.set_signer(signer.clone());
+        document.add(&Item::new("signed"));
+        let held = document.signatures().clone();
+
+        let mut squashed = squash(&document.save().unwrap(), None, Some(signer.clone())).unwrap();
+        assert_eq!(schema::version_of(squashed.state()).unwrap(), SCHEMA_VERSION);
+        // A peer which rejects anything unsigned takes every new change
+        let initial = Document::default().change_hashes();
+        let changes: Vec<automerge::Change> = automerge::Change::load_document(&squashed.save().unwrap())
+            .unwrap()
+            .into_iter()
+            .filter(|change| !initial.contains(&signing::hash_of(change)))
+            .collect();
+        let keyring = Keyring {
+            trusted: vec![("me".to_string(), signer.public_key())].into_iter().collect(),
+            reject_unknown: true,
+        };
+        let (accepted, flagged) = signing::verify(changes.clone(), squashed.signatures(), &Default::default(), &keyring, None);
+        assert!(flagged.is_empty(), "{:?}", flagged);
+        assert_eq!(accepted.len(), changes.len());
+
+        // Swapping it in keeps the signatures held before too
+        let new = squashed.signatures().clone();
+        document.replace_history(squashed).unwrap();
+        assert!(held.keys().chain(new.keys()).all(|hash| document.signatures().contains_key(hash)));
+        assert_eq!(schema::version_of(document.state()).unwrap(), SCHEMA_VERSION);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Where the application keeps its configuration, under the XDG config
+/// directory
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("todomvc"))
+}
+
+/// A name for this installation which stays the same between runs, unlike
+/// the actor id automerge picks for each session. Created the first time
+/// it's asked for.
+pub fn peer_id() -> io::Result<String> {
+    let dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    let path = dir.join("peer-id");
+    match fs::read_to_string(&path) {
+        Ok(id) if !id.trim().is_empty() => Ok(id.trim().to_string()),
+        Ok(_) => write_peer_id(&path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            fs::create_dir_all(&dir)?;
+            write_peer_id(&path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn write_peer_id(path: &Path) -> io::Result<String> {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    fs::write(path, &id)?;
+    Ok(id)
+}
+
+/// How to authenticate with a remote
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Credentials {
+    Bearer { token: String },
+    Basic { username: String, password: Option<String> },
+}
+
+impl Credentials {
+    /// The value of the `Authorization` header to send
+    pub fn authorization(&self) -> String {
+        match self {
+            Credentials::Bearer { token } => format!("Bearer {}", token),
+            Credentials::Basic { username, password } => {
+                let pair = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                format!("Basic {}", base64::encode(pair))
+            }
+        }
+    }
+}
+
+// Keep secrets out of logs
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Bearer { .. } => write!(f, "Bearer(..)"),
+            Credentials::Basic { username, .. } => write!(f, "Basic({}, ..)", username),
+        }
+    }
+}
+
+/// Where the credentials for each remote are kept. Only the user may read
+/// or write the file.
+pub fn credentials_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("credentials.json"))
+}
+
+/// The credentials for each remote, by location. Refuses to read them if
+/// anyone but the user can.
+pub fn load_credentials() -> Result<BTreeMap<String, Credentials>, String> {
+    let path = credentials_path().ok_or("no config directory")?;
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let mode = fs::metadata(&path).map_err(|e| format!("{}: {}", path.display(), e))?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(format!("{}: readable by other users, run chmod 600 on it", path.display()));
+    }
+    serde_json::from_slice(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Stores the credentials for a remote, or forgets them if `credentials` is
+/// `None`
+pub fn set_credentials(location: &str, credentials: Option<Credentials>) -> Result<(), String> {
+    let mut all = load_credentials()?;
+    match credentials {
+        Some(credentials) => all.insert(location.to_string(), credentials),
+        None => all.remove(location),
+    };
+    let path = credentials_path().ok_or("no config directory")?;
+    let data = serde_json::to_vec_pretty(&all).map_err(|e| e.to_string())?;
+    let write = || -> io::Result<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&path)?;
+        // `mode` only applies to new files
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(&data)
+    };
+    write().map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,95 @@
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+/// Starts every encrypted document, so we can tell one from a plain
+/// automerge document. The digit is the format's version, to be bumped if
+/// the key derivation or cipher ever changes.
+const MAGIC: &[u8] = b"TODOENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Argon2id's memory cost in KiB, passes and lanes. These are the argon2
+/// crate's defaults when this was written, spelled out because documents
+/// encrypted with them have to stay readable if the crate's defaults change.
+const ARGON2_M_COST: u32 = 4096;
+const ARGON2_T_COST: u32 = 3;
+const ARGON2_P_COST: u32 = 1;
+
+/// Derives the key for a passphrase with Argon2id
+fn key(passphrase: &str, salt: &[u8]) -> Result<Key, String> {
+    let mut key = Key::default();
+    let params = argon2::Params::new(ARGON2_M_COST, ARGON2_T_COST, ARGON2_P_COST, Some(key.len()))
+        .map_err(|e| e.to_string())?;
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+/// Whether `data` was written by `encrypt`
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts a saved document with a key derived from `passphrase`.
+///
+/// The result is `MAGIC`, a random salt for the key, a random nonce, then
+/// the document sealed with XChaCha20-Poly1305, so nobody without the
+/// passphrase can read or alter it.
+pub fn encrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = XChaCha20Poly1305::new(&key(passphrase, &salt)?);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, data).map_err(|_| "Encryption failed".to_string())?;
+    let mut encrypted = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + sealed.len());
+    encrypted.extend_from_slice(MAGIC);
+    encrypted.extend_from_slice(&salt);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&sealed);
+    Ok(encrypted)
+}
+
+/// Decrypts a document written by `encrypt`
+pub fn decrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_encrypted(data) {
+        return Err("The document isn't encrypted, refusing to trust it".to_string());
+    }
+    let data = &data[MAGIC.len()..];
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err("The encrypted document is truncated".to_string());
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    let cipher = XChaCha20Poly1305::new(&key(passphrase, salt)?);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), sealed)
+        .map_err(|_| "Wrong passphrase, or the encrypted document has been tampered with".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_with_the_right_passphrase() {
+        let encrypted = encrypt("correct horse", b"a document").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(decrypt("correct horse", &encrypted).unwrap(), b"a document");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_an_error() {
+        let encrypted = encrypt("correct horse", b"a document").unwrap();
+        let error = decrypt("battery staple", &encrypted).unwrap_err();
+        assert!(error.starts_with("Wrong passphrase"), "{}", error);
+    }
+
+    #[test]
+    fn truncated_and_plain_documents_are_errors() {
+        let encrypted = encrypt("correct horse", b"a document").unwrap();
+        assert!(decrypt("correct horse", &encrypted[..MAGIC.len() + 3]).is_err());
+        assert!(decrypt("correct horse", b"a document").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc::UnboundedSender;
+
+use zbus::{dbus_interface, fdo, Connection, ObjectServer};
+
+use crate::document::Document;
+use crate::items::Item;
+
+/// The bus name the service is on. It isn't the application id, which
+/// GApplication owns already.
+pub const NAME: &str = "camp.lol.todomvc.Todos";
+pub const PATH: &str = "/camp/lol/todomvc/Todos";
+pub const INTERFACE: &str = "camp.lol.todomvc.Todos";
+
+/// How long a call waits for the application to answer it
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A method call on the interface
+#[derive(Clone, Debug)]
+pub enum Call {
+    AddTodo { task: String },
+    ListTodos,
+    Toggle { id: String },
+    Sync,
+}
+
+#[derive(Clone, Debug)]
+pub enum Reply {
+    Added { id: String },
+    Todos(Vec<Item>),
+    Done,
+}
+
+/// Whatever carries out calls to the interface
+pub trait Todos: Send + Sync + 'static {
+    fn handle(&self, call: Call) -> Result<Reply, String>;
+}
+
+/// Answers a call on `document`. Syncing needs the remotes, which the
+/// document doesn't know about, so `Sync` is left to the caller and
+/// answered here without doing anything.
+pub fn answer(document: &mut Document, call: Call) -> Result<Reply, String> {
+    match call {
+        Call::AddTodo { task } => {
+            if task.trim().is_empty() {
+                return Err("A todo needs a task".to_string());
+            }
+            let item = Item::new(task.trim());
+            document.add(&item);
+            Ok(Reply::Added { id: item.id })
+        }
+        Call::ListTodos => Ok(Reply::Todos(document.items().iter().filter(|item| !item.is_deleted()).cloned().collect())),
+        Call::Toggle { id } => {
+            if !document.items().iter().any(|item| item.id == id && !item.is_deleted()) {
+                return Err(format!("No todo with id {}", id));
+            }
+            document.toggle(&id);
+            Ok(Reply::Done)
+        }
+        Call::Sync => Ok(Reply::Done),
+    }
+}
+
+/// A call waiting for the application to answer it
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub call: Call,
+    reply: mpsc::Sender<Result<Reply, String>>,
+}
+
+impl Request {
+    pub fn reply(self, result: Result<Reply, String>) {
+        // The caller may have given up waiting
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Hands each call on to `requests` and waits for the answer, so the
+/// application can handle calls on its own thread like any other event
+pub struct Forward(pub UnboundedSender<Request>);
+
+impl Todos for Forward {
+    fn handle(&self, call: Call) -> Result<Reply, String> {
+        let (reply, answer) = mpsc::channel();
+        self.0
+            .unbounded_send(Request { call, reply })
+            .map_err(|_| "The application is shutting down".to_string())?;
+        answer
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| "The application didn't answer".to_string())?
+    }
+}
+
+struct Service {
+    todos: Box<dyn Todos>,
+}
+
+impl Service {
+    fn call(&self, call: Call) -> fdo::Result<Reply> {
+        self.todos.handle(call).map_err(fdo::Error::Failed)
+    }
+}
+
+fn unexpected(reply: Reply) -> fdo::Error {
+    fdo::Error::Failed(format!("Unexpected reply {:?}", reply))
+}
+
+#[dbus_interface(name = "camp.lol.todomvc.Todos")]
+impl Service {
+    /// Adds a todo, returns its id
+    fn add_todo(&self, task: String) -> fdo::Result<String> {
+        match self.call(Call::AddTodo { task })? {
+            Reply::Added { id } => Ok(id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Every todo which isn't in the trash, as (id, task, completed)
+    fn list_todos(&self) -> fdo::Result<Vec<(String, String, bool)>> {
+        match self.call(Call::ListTodos)? {
+            Reply::Todos(items) => Ok(items.into_iter().map(|item| (item.id, item.task, item.done)).collect()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Completes or reopens the todo with the given id
+    fn toggle(&self, id: String) -> fdo::Result<()> {
+        self.call(Call::Toggle { id }).map(|_| ())
+    }
+
+    /// Pulls from and pushes to every remote
+    fn sync(&self) -> fdo::Result<()> {
+        self.call(Call::Sync).map(|_| ())
+    }
+}
+
+/// Connects to the bus at `address`, the session bus if it's `None`, and
+/// takes the service's name
+pub fn connect(address: Option<&str>) -> Result<Connection, String> {
+    let connection = match address {
+        Some(address) => Connection::new_for_address(address, true),
+        None => Connection::new_session(),
+    }
+    .map_err(|e| e.to_string())?;
+    let reply = fdo::DBusProxy::new(&connection)
+        .and_then(|bus| bus.request_name(NAME, fdo::RequestNameFlags::DoNotQueue.into()))
+        .map_err(|e| e.to_string())?;
+    match reply {
+        fdo::RequestNameReply::PrimaryOwner | fdo::RequestNameReply::AlreadyOwner => Ok(connection),
+        _ => Err(format!("{} is taken, is the application already running?", NAME)),
+    }
+}
+
+/// Answers calls to the interface on `connection` with `todos`, on a
+/// thread of its own, until the connection is closed
+pub fn serve<T: Todos>(connection: Connection, todos: T) -> Result<(), String> {
+    let mut server = ObjectServer::new(&connection);
+    server.at(PATH, Service { todos: Box::new(todos) }).map_err(|e| e.to_string())?;
+    thread::spawn(move || loop {
+        // Calls which fail are answered with the error, anything else which
+        // goes wrong means the connection is gone
+        if let Err(zbus::Error::Io(_)) = server.try_handle_next() {
+            return;
+        }
+    });
+    Ok(())
+}
+
+/// Tells everyone listening that the todos have changed
+pub fn changed(connection: &Connection) -> Result<(), String> {
+    connection.emit_signal(None, PATH, INTERFACE, "Changed", &()).map_err(|e| e.to_string())
+}
@@ -0,0 +1,1133 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path as FsPath;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use automerge::{InvalidChangeRequest, LocalChange, MutableDocument, Path, ScalarValue, Value};
+use automerge_protocol as amp;
+use chrono::NaiveDate;
+
+use maplit::hashmap;
+
+use crate::items::{Entry, Item, Items, DUE_FORMAT};
+use crate::journal::{self, Journal, Recovered};
+use crate::recurrence::{successor_id, Recurrence};
+use crate::schema;
+use crate::signing::{self, Flagged, Keyring, Signatures, Signer};
+
+/// Where the signatures of the autosaved document are kept as of its last
+/// snapshot, in the journal's directory. Those made since are in the
+/// journal's signature log.
+const SIGNATURES: &str = "signatures.json";
+
+/// A todo document: the automerge backend and frontend plus a decoded view
+/// of the `todos` sequence.
+///
+/// The view is kept up to date from the patches automerge hands back, so
+/// only the entries a patch touches get decoded again.
+pub struct Document {
+    backend: automerge::Backend,
+    frontend: automerge::Frontend,
+    /// The object id of the `todos` sequence the entries were decoded from
+    todos: Option<amp::ObjectID>,
+    entries: Vec<Entry>,
+    items: Arc<Items>,
+    /// The hex hash of every change in the backend, kept up to date as
+    /// changes are applied so that it isn't walked on every render
+    hashes: Arc<HashSet<String>>,
+    /// Where every change is recorded as it's made, if autosave is on
+    journal: Option<Journal>,
+    autosave_error: Option<String>,
+    /// Everyone who wants to hear about local changes, e.g. live sync
+    subscribers: Vec<Sender<Vec<u8>>>,
+    /// Signs each local change, if set
+    signer: Option<Arc<Signer>>,
+    /// The signatures of the changes we hold, ours and those pulled
+    signatures: Signatures,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        let mut backend = automerge::Backend::init();
+        let change = automerge::Change::from_bytes(crate::INIT_CHANGE.to_vec()).unwrap();
+        backend.apply_changes(vec![change]).unwrap();
+        Document::from_backend(backend).unwrap()
+    }
+}
+
+impl Document {
+    /// Loads a document saved with `Document::save`. Refuses documents
+    /// written by a newer schema, older ones are upgraded by `migrate`.
+    pub fn load(data: Vec<u8>) -> Result<Document, String> {
+        let backend = untrusted(|| automerge::Backend::load(data).map_err(|e| format!("{:?}", e)))?;
+        Document::from_backend(backend)
+    }
+
+    /// Recovers the document autosaved in `dir`, from its last snapshot plus
+    /// every change journaled since, and keeps autosaving there
+    pub fn recover(dir: &FsPath) -> Result<Document, String> {
+        let (journal, recovered) = Journal::open(dir).map_err(|e| e.to_string())?;
+        Document::from_journal(journal, recovered)
+    }
+
+    /// Recovers every list autosaved under `base` which no other instance
+    /// has open, or starts a new one there if there aren't any, so instances
+    /// running at the same time never share an autosave
+    pub fn recover_free(base: &FsPath) -> Result<Vec<Document>, String> {
+        let mut documents = Vec::new();
+        for dir in journal::lists(base).map_err(|e| format!("{}: {}", base.display(), e))? {
+            match Journal::open(&dir) {
+                Ok((journal, recovered)) => documents.push(Document::from_journal(journal, recovered)?),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(format!("{}: {}", dir.display(), e)),
+            }
+        }
+        if documents.is_empty() {
+            documents.push(Document::recover(&journal::new_list(base))?);
+        }
+        Ok(documents)
+    }
+
+    fn from_journal(journal: Journal, recovered: Recovered) -> Result<Document, String> {
+        let dir = journal.dir().to_path_buf();
+        let mut document = match recovered.snapshot {
+            Some(snapshot) => Document::load(snapshot)?,
+            None => Document::default(),
+        };
+        // A record which doesn't decode can only be the remains of a crash
+        let changes = recovered
+            .changes
+            .into_iter()
+            .filter_map(|bytes| automerge::Change::from_bytes(bytes).ok())
+            .collect();
+        document.apply_changes(changes)?;
+        document.signatures = signing::load_signatures(&dir.join(SIGNATURES))?;
+        for record in recovered.signatures {
+            if let Ok(signatures) = signing::parse_signatures(&record) {
+                document.signatures.extend(signatures);
+            }
+        }
+        document.journal = Some(journal);
+        document.migrate()?;
+        document.compact()?;
+        Ok(document)
+    }
+
+    /// A scratch copy of the document, with the same history and signer,
+    /// which isn't autosaved and doesn't notify anyone of its changes
+    pub fn fork(&self) -> Result<Document, String> {
+        let mut fork = Document::load(self.save()?)?;
+        fork.signer = self.signer.clone();
+        fork.signatures = self.signatures.clone();
+        Ok(fork)
+    }
+
+    /// Builds a document from a set of changes, which must include their
+    /// dependencies
+    pub fn from_changes(changes: Vec<automerge::Change>) -> Result<Document, String> {
+        let mut backend = automerge::Backend::init();
+        backend.apply_changes(changes).map_err(|e| format!("{:?}", e))?;
+        Document::from_backend(backend)
+    }
+
+    /// Swaps in a document with different history, e.g. a squashed one,
+    /// keeping our autosave, signer and the signatures we held, which
+    /// anyone still holding the old history can go on checking against
+    pub fn replace_history(&mut self, mut other: Document) -> Result<(), String> {
+        other.journal = self.journal.take();
+        other.subscribers = std::mem::take(&mut self.subscribers);
+        other.signer = self.signer.take();
+        let mut signatures = std::mem::take(&mut self.signatures);
+        signatures.extend(std::mem::take(&mut other.signatures));
+        other.signatures = signatures;
+        *self = other;
+        self.compact()
+    }
+
+    fn from_backend(backend: automerge::Backend) -> Result<Document, String> {
+        let patch = backend.get_patch().map_err(|e| format!("{:?}", e))?;
+        let mut frontend = automerge::Frontend::new();
+        frontend.apply_patch(patch).map_err(|e| format!("{:?}", e))?;
+        let hashes = backend.get_changes(&[]).into_iter().map(signing::hash_of).collect();
+        let mut document = Document {
+            backend,
+            frontend,
+            todos: None,
+            entries: Vec::new(),
+            items: Arc::new(Items::default()),
+            hashes: Arc::new(hashes),
+            journal: None,
+            autosave_error: None,
+            subscribers: Vec::new(),
+            signer: None,
+            signatures: Signatures::new(),
+        };
+        document.rebuild();
+        schema::check(document.state()).map_err(|e| e.to_string())?;
+        Ok(document)
+    }
+
+    /// Upgrades the document to the current schema, writing a change for
+    /// each migration. Only done to documents the user edits, not to
+    /// scratch copies and new lists, each of which would write its own.
+    pub fn migrate(&mut self) -> Result<bool, String> {
+        schema::migrate(self).map_err(|e| e.to_string())
+    }
+
+    pub fn items(&self) -> Arc<Items> {
+        self.items.clone()
+    }
+
+    pub fn state(&mut self) -> &Value {
+        self.frontend.state()
+    }
+
+    pub fn save(&self) -> Result<Vec<u8>, String> {
+        self.backend.save().map_err(|e| format!("{:?}", e))
+    }
+
+    /// Every change in the document's history, encoded
+    pub fn all_changes(&self) -> Vec<Vec<u8>> {
+        self.backend
+            .get_changes(&[])
+            .into_iter()
+            .map(|change| change.raw_bytes().to_vec())
+            .collect()
+    }
+
+    /// The hex hashes of every change in the document's history
+    pub fn change_hashes(&self) -> Arc<HashSet<String>> {
+        self.hashes.clone()
+    }
+
+    /// Receives the encoded bytes of every local change made from now on
+    pub fn subscribe_local_changes(&mut self) -> Receiver<Vec<u8>> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Folds the autosave journal into a fresh snapshot, and the signature
+    /// log into the signatures file
+    pub fn compact(&mut self) -> Result<(), String> {
+        if self.journal.is_none() {
+            return Ok(());
+        }
+        let snapshot = self.save()?;
+        let journal = self.journal.as_mut().unwrap();
+        signing::save_signatures(&journal.dir().join(SIGNATURES), &self.signatures)
+            .and_then(|_| journal.compact(&snapshot))
+            .map_err(|e| format!("Autosave failed: {}", e))
+    }
+
+    /// Signs every local change made from now on with `signer`
+    pub fn set_signer(&mut self, signer: Arc<Signer>) {
+        self.signer = Some(signer);
+    }
+
+    /// The signatures of the changes we hold, to store alongside the
+    /// document
+    pub fn signatures(&self) -> &Signatures {
+        &self.signatures
+    }
+
+    /// Keeps the signatures of changes we've just made or taken in,
+    /// appending them to the autosave rather than rewriting every signature
+    /// we hold
+    fn keep_signatures(&mut self, signatures: Signatures) {
+        if signatures.is_empty() {
+            return;
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.append_signatures(&signing::encode_signatures(&signatures)) {
+                self.autosave_error = Some(format!("Autosave failed: {}", e));
+            }
+        }
+        self.signatures.extend(signatures);
+    }
+
+    /// The directory the document is autosaved in, which identifies its list
+    /// across restarts
+    pub fn autosave_dir(&self) -> Option<&FsPath> {
+        self.journal.as_ref().map(Journal::dir)
+    }
+
+    /// Why the last change couldn't be autosaved, if it couldn't
+    pub fn autosave_error(&self) -> Option<&str> {
+        self.autosave_error.as_deref()
+    }
+
+    /// Stops showing why the last change couldn't be autosaved, until the
+    /// next one can't be either
+    pub fn dismiss_autosave_error(&mut self) {
+        self.autosave_error = None;
+    }
+
+    /// Whether every change is autosaved, so there's nothing to lose by not
+    /// saving to a file
+    pub fn is_autosaved(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Stops autosaving and deletes the autosave, e.g. when its list is
+    /// closed for good
+    pub fn discard_autosave(&mut self) -> Result<(), String> {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return Ok(()),
+        };
+        let dir = journal.dir().to_path_buf();
+        // Unlocks it, so nothing else can be holding it open
+        drop(journal);
+        std::fs::remove_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))
+    }
+
+    fn autosave(&mut self, changes: &[&[u8]]) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
+        let result = changes
+            .iter()
+            .try_for_each(|change| journal.append(change))
+            .map_err(|e| format!("Autosave failed: {}", e));
+        self.autosave_error = result.err();
+        if self.autosave_error.is_none() && journal.len() >= journal::COMPACT_EVERY {
+            self.autosave_error = self.compact().err();
+        }
+    }
+
+    /// The object id at `path`, formatted for display
+    pub fn object_id(&self, path: &Path) -> Option<String> {
+        self.frontend.get_object_id(path).map(|id| format!("{:?}", id))
+    }
+
+    /// Makes a local change, returns whether anything changed
+    pub fn change<F>(&mut self, message: Option<String>, f: F) -> Result<bool, String>
+    where
+        F: FnOnce(&mut dyn MutableDocument) -> Result<(), InvalidChangeRequest>,
+    {
+        let change = self
+            .frontend
+            .change::<_, InvalidChangeRequest>(message, f)
+            .map_err(|e| format!("{:?}", e))?;
+        match change {
+            Some(change) => {
+                let (patch, change) = self
+                    .backend
+                    .apply_local_change(change)
+                    .map_err(|e| format!("{:?}", e))?;
+                self.apply_patch(patch)?;
+                Arc::make_mut(&mut self.hashes).insert(signing::hash_of(&change));
+                self.autosave(&[change.raw_bytes()]);
+                if let Some(signer) = self.signer.as_ref() {
+                    let mut signatures = Signatures::new();
+                    signatures.insert(signing::hash_of(&change), signer.sign(&change));
+                    self.keep_signatures(signatures);
+                }
+                let raw = change.raw_bytes();
+                self.subscribers.retain(|subscriber| subscriber.send(raw.to_vec()).is_ok());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Merges a saved document, e.g. one pulled from a remote, into ours and
+    /// upgrades the result to the current schema. Refuses to merge anything
+    /// we wouldn't be able to read afterwards.
+    pub fn merge(&mut self, data: &[u8]) -> Result<(), String> {
+        self.apply_changes(load_untrusted(data)?)?;
+        self.migrate()?;
+        Ok(())
+    }
+
+    /// Merges a saved document like `merge`, but first checks the
+    /// signatures of every change we don't already have against `keyring`.
+    /// Rejected changes, and any which depend on them, aren't merged.
+    /// Returns the changes which were rejected or merged despite not being
+    /// signed by anyone we trust.
+    pub fn merge_verified(&mut self, data: &[u8], signatures: &Signatures, keyring: &Keyring) -> Result<Vec<Flagged>, String> {
+        let flagged = self.apply_verified(load_untrusted(data)?, signatures, keyring)?;
+        self.migrate()?;
+        Ok(flagged)
+    }
+
+    /// Applies changes from elsewhere, e.g. live sync or a peer, checking
+    /// the signatures of those we don't already have against `keyring` the
+    /// way `merge_verified` does. Returns the changes which were flagged.
+    pub fn apply_verified(
+        &mut self,
+        changes: Vec<automerge::Change>,
+        signatures: &Signatures,
+        keyring: &Keyring,
+    ) -> Result<Vec<Flagged>, String> {
+        let have = self.change_hashes();
+        let new = changes.into_iter().filter(|c| !have.contains(&signing::hash_of(c))).collect();
+        let own_key = self.signer.as_ref().map(|signer| signer.public_key());
+        let (accepted, flagged) = signing::verify(new, signatures, &self.signatures, keyring, own_key.as_deref());
+        let accepted_signatures = accepted
+            .iter()
+            .filter_map(|change| {
+                let hash = signing::hash_of(change);
+                signatures.get(&hash).map(|signature| (hash, signature.clone()))
+            })
+            .collect();
+        self.apply_changes(accepted)?;
+        self.keep_signatures(accepted_signatures);
+        Ok(flagged)
+    }
+
+    /// Applies changes from elsewhere, e.g. a remote
+    pub fn apply_changes(&mut self, changes: Vec<automerge::Change>) -> Result<(), String> {
+        let raw: Vec<Vec<u8>> = match self.journal {
+            Some(_) => changes.iter().map(|c| c.raw_bytes().to_vec()).collect(),
+            None => Vec::new(),
+        };
+        let heads = self.backend.get_heads();
+        let patch = self
+            .backend
+            .apply_changes(changes)
+            .map_err(|e| format!("{:?}", e))?;
+        // Changes still waiting for their dependencies haven't been applied,
+        // so ask the backend which ones were rather than taking them all
+        let applied: Vec<String> = self.backend.get_changes(&heads).into_iter().map(signing::hash_of).collect();
+        Arc::make_mut(&mut self.hashes).extend(applied);
+        self.apply_patch(patch)?;
+        self.autosave(&raw.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        self.drop_duplicates()
+    }
+
+    /// Merges copies of the same recurring item's successor. Peers which
+    /// complete the same recurring item at the same time each add its
+    /// successor, under an id worked out from the completed item's, and may
+    /// go on to edit their copy before they sync. The first copy is kept and
+    /// the others are folded into it: it's done or in the trash if any copy
+    /// is, takes the latest due date, and takes the task or repeat rule from
+    /// a copy where it was changed from the completed item's. Every peer sees
+    /// the copies in the same order, so they all end up with the same item.
+    /// Items which share an id for any other reason are left alone.
+    fn drop_duplicates(&mut self) -> Result<(), String> {
+        let items: Vec<(usize, &Item)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry {
+                Entry::Item(item) => Some((index, item)),
+                Entry::Problem { .. } => None,
+            })
+            .collect();
+        let predecessors: HashMap<String, &Item> = items.iter().map(|(_, item)| (successor_id(&item.id), *item)).collect();
+        let mut kept: HashMap<&str, (u32, &Item, Item)> = HashMap::new();
+        let mut duplicates = Vec::new();
+        for (index, item) in &items {
+            let predecessor = match predecessors.get(&item.id) {
+                Some(predecessor) => predecessor,
+                None => continue,
+            };
+            match kept.get_mut(item.id.as_str()) {
+                Some((_, _, copy)) => {
+                    merge_copies(copy, item, predecessor);
+                    duplicates.push(*index as u32);
+                }
+                None => {
+                    kept.insert(item.id.as_str(), (*index as u32, *item, (*item).clone()));
+                }
+            }
+        }
+        if duplicates.is_empty() {
+            return Ok(());
+        }
+        let mut merged: Vec<(u32, Item, Item)> = kept
+            .into_iter()
+            .map(|(_, (index, original, item))| (index, original.clone(), item))
+            .collect();
+        merged.sort_by_key(|(index, _, _)| *index);
+        self.change(Some("Remove duplicate items".to_string()), |doc| {
+            for (index, original, item) in &merged {
+                set_fields(doc, *index, original, item)?;
+            }
+            for index in duplicates.into_iter().rev() {
+                doc.add_change(LocalChange::delete(Path::root().key("todos").index(index)))?;
+            }
+            Ok(())
+        })
+        .map(|_| ())
+    }
+
+    fn apply_patch(&mut self, patch: amp::Patch) -> Result<(), String> {
+        let diff = patch.diffs.clone();
+        self.frontend
+            .apply_patch(patch)
+            .map_err(|e| format!("{:?}", e))?;
+        self.update_items(diff.as_ref());
+        Ok(())
+    }
+
+    /// Brings the entries up to date with a patch which has already been
+    /// applied to the frontend
+    fn update_items(&mut self, diff: Option<&amp::Diff>) {
+        let root = match diff {
+            Some(amp::Diff::Map(root)) => root,
+            _ => return,
+        };
+        let todos = match root.props.get("todos") {
+            Some(todos) => todos,
+            None => return,
+        };
+        let seq = match todos.values().next() {
+            Some(amp::Diff::Seq(seq)) if todos.len() == 1 && Some(&seq.object_id) == self.todos.as_ref() => seq,
+            Some(amp::Diff::Unchanged(_)) if todos.len() == 1 => return,
+            // `todos` was replaced or is conflicted, start again
+            _ => return self.rebuild(),
+        };
+        let mut dirty: BTreeSet<usize> = BTreeSet::new();
+        for edit in seq.edits.iter() {
+            match edit {
+                amp::DiffEdit::Insert { index, .. } => {
+                    let index = *index;
+                    self.entries.insert(index, Entry::decode(None, None));
+                    dirty = dirty.into_iter().map(|i| if i >= index { i + 1 } else { i }).collect();
+                    dirty.insert(index);
+                }
+                amp::DiffEdit::Remove { index } => {
+                    let index = *index;
+                    self.entries.remove(index);
+                    dirty = dirty
+                        .into_iter()
+                        .filter(|i| *i != index)
+                        .map(|i| if i > index { i - 1 } else { i })
+                        .collect();
+                }
+            }
+        }
+        dirty.extend(seq.props.keys().copied());
+        for index in dirty {
+            let path = Path::root().key("todos").index(index.try_into().unwrap());
+            let value = self.frontend.get_value(&path);
+            let object_id = self.object_id(&path);
+            self.entries[index] = Entry::decode(value.as_ref(), object_id);
+        }
+        self.items = Arc::new(Items::from_entries(&self.entries));
+    }
+
+    /// Decodes every entry from scratch
+    fn rebuild(&mut self) {
+        let todos_path = Path::root().key("todos");
+        self.todos = self.frontend.get_object_id(&todos_path);
+        let state = self.frontend.state().clone();
+        let frontend = &self.frontend;
+        let decoded = Entry::decode_all(&state, |index| {
+            let path = Path::root().key("todos").index(index as u32);
+            frontend.get_object_id(&path).map(|id| format!("{:?}", id))
+        });
+        match decoded {
+            Ok(entries) => {
+                self.items = Arc::new(Items::from_entries(&entries));
+                self.entries = entries;
+            }
+            Err(reason) => {
+                self.entries = Vec::new();
+                self.items = Arc::new(Items::broken(&reason));
+            }
+        }
+    }
+
+    /// The index in the `todos` sequence and the completed state of each
+    /// item with one of the given ids, read from the decoded entries so large
+    /// documents don't have to be walked through the frontend
+    fn find(&self, ids: &[String]) -> Vec<(u32, bool)> {
+        let wanted: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry {
+                Entry::Item(item) if wanted.contains(item.id.as_str()) => Some((index as u32, item.done)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn add(&mut self, item: &Item) {
+        let index = self.entries.len() as u32;
+        self.change(Some("Add item".to_string()), |doc| {
+            doc.add_change(LocalChange::insert(Path::root().key("todos").index(index), item_value(item)))?;
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Makes the `todos` sequence hold exactly `items`, in order, in one
+    /// change. Malformed entries are dropped.
+    pub fn set_items(&mut self, message: Option<String>, items: &[Item]) -> Result<bool, String> {
+        let existing: HashMap<String, Item> = self
+            .items
+            .iter()
+            .map(|item| (item.id.clone(), item.clone()))
+            .collect();
+        let wanted: HashSet<&str> = items.iter().map(|item| item.id.as_str()).collect();
+        let mut ids: Vec<Option<String>> = self
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::Item(item) => Some(item.id.clone()),
+                Entry::Problem { .. } => None,
+            })
+            .collect();
+        self.change(message, |doc| {
+            let todo = |index: usize| Path::root().key("todos").index(index as u32);
+            for index in (0..ids.len()).rev() {
+                if !ids[index].as_deref().map_or(false, |id| wanted.contains(id)) {
+                    doc.add_change(LocalChange::delete(todo(index)))?;
+                    ids.remove(index);
+                }
+            }
+            for (index, item) in items.iter().enumerate() {
+                if ids.get(index).and_then(|id| id.as_deref()) == Some(item.id.as_str()) {
+                    set_fields(doc, index as u32, &existing[&item.id], item)?;
+                    continue;
+                }
+                if let Some(old) = ids.iter().position(|id| id.as_deref() == Some(item.id.as_str())) {
+                    doc.add_change(LocalChange::delete(todo(old)))?;
+                    ids.remove(old);
+                }
+                doc.add_change(LocalChange::insert(todo(index), item_value(item)))?;
+                ids.insert(index, Some(item.id.clone()));
+            }
+            Ok(())
+        })
+    }
+
+    /// Sets or clears the due date of the item with the given id
+    pub fn set_due(&mut self, id: &str, due: Option<NaiveDate>) {
+        let found = self.find(&[id.to_string()]);
+        self.change(Some("Set due date".to_string()), |doc| {
+            for (index, _) in found {
+                set_optional(doc, index, "due", due.map(due_value))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Moves the item with the given id to the trash
+    pub fn remove(&mut self, id: &str) {
+        let found = self.find(&[id.to_string()]);
+        self.trash(found.into_iter().map(|(index, _)| index).collect());
+    }
+
+    /// Takes the item with the given id back out of the trash
+    pub fn restore(&mut self, id: &str) {
+        let found = self.find(&[id.to_string()]);
+        self.change(Some("Restore item".to_string()), |doc| {
+            for (index, _) in found {
+                set_optional(doc, index, "deletedAt", None)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Deletes every item in the trash for good
+    pub fn empty_trash(&mut self) {
+        let trashed = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry {
+                Entry::Item(item) if item.is_deleted() => Some(index as u32),
+                _ => None,
+            })
+            .collect();
+        self.delete(trashed);
+    }
+
+    pub fn toggle(&mut self, id: &str) {
+        self.toggle_all(&[id.to_string()]);
+    }
+
+    /// Flips the completed state of each of the items with the given ids
+    pub fn toggle_all(&mut self, ids: &[String]) {
+        self.toggle_all_on(ids, chrono::Local::today().naive_local());
+    }
+
+    /// Flips the completed state of each of the items with the given ids as
+    /// if it were `today`. Completing a recurring item adds its next
+    /// occurrence in the same change, unless it's already there.
+    pub fn toggle_all_on(&mut self, ids: &[String], today: NaiveDate) {
+        let found = self.find(ids);
+        let mut existing: HashSet<String> = self.items.iter().map(|item| item.id.clone()).collect();
+        let successors: Vec<Item> = found
+            .iter()
+            .filter(|(_, done)| !done)
+            .filter_map(|(index, _)| match &self.entries[*index as usize] {
+                Entry::Item(item) => item.successor(today),
+                Entry::Problem { .. } => None,
+            })
+            .filter(|successor| existing.insert(successor.id.clone()))
+            .collect();
+        let end = self.entries.len() as u32;
+        self.change(None, |doc| {
+            for (index, done) in found {
+                doc.add_change(LocalChange::set(
+                    Path::root().key("todos").index(index).key("completed"),
+                    Value::Primitive(ScalarValue::Boolean(!done)),
+                ))?;
+            }
+            for (offset, successor) in successors.iter().enumerate() {
+                doc.add_change(LocalChange::insert(Path::root().key("todos").index(end + offset as u32), item_value(successor)))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Sets or clears the recurrence of the item with the given id
+    pub fn set_recurrence(&mut self, id: &str, recurrence: Option<&Recurrence>) {
+        let found = self.find(&[id.to_string()]);
+        self.change(Some("Set recurrence".to_string()), |doc| {
+            for (index, _) in found {
+                set_optional(doc, index, "recurrence", recurrence.map(recurrence_value))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Changes the task of the item with the given id
+    pub fn rename(&mut self, id: &str, task: &str) {
+        let found = self.find(&[id.to_string()]);
+        self.change(Some("Edit item".to_string()), |doc| {
+            for (index, _) in found {
+                doc.add_change(LocalChange::set(
+                    Path::root().key("todos").index(index).key("value"),
+                    Value::Primitive(task.into()),
+                ))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Moves every completed item to the trash
+    pub fn clear_completed(&mut self) {
+        let completed = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry {
+                Entry::Item(item) if item.done && !item.is_deleted() => Some(index as u32),
+                _ => None,
+            })
+            .collect();
+        self.trash(completed);
+    }
+
+    /// Marks the entries at the given indices deleted. They stay in `todos`,
+    /// so a peer can still restore them after merging the deletion.
+    fn trash(&mut self, indices: Vec<u32>) {
+        let now = chrono::Utc::now().timestamp_millis();
+        self.change(Some("Move to trash".to_string()), |doc| {
+            for index in indices {
+                doc.add_change(LocalChange::set(
+                    Path::root().key("todos").index(index).key("deletedAt"),
+                    Value::Primitive(ScalarValue::Int(now)),
+                ))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Deletes the entries at the given ascending indices in one change
+    fn delete(&mut self, indices: Vec<u32>) {
+        self.change(None, |doc| {
+            // Back to front so earlier deletes don't shift later indices
+            for index in indices.into_iter().rev() {
+                doc.add_change(LocalChange::delete(Path::root().key("todos").index(index)))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Replaces the malformed entry at `index` with a repaired one, or
+    /// deletes it. Does nothing if the entry at `index` is no longer
+    /// `object_id`.
+    pub fn fix_problem(&mut self, index: usize, object_id: Option<String>, repair: bool) {
+        let path = Path::root().key("todos").index(index as u32);
+        if object_id.is_some() && self.object_id(&path) != object_id {
+            return;
+        }
+        self.change(None, |doc| {
+            if repair {
+                let value = repaired(doc, index as u32);
+                doc.add_change(LocalChange::set(path.clone(), value))?;
+            } else {
+                doc.add_change(LocalChange::delete(path.clone()))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+}
+
+/// Runs `decode` on bytes we got from elsewhere. automerge's decoders
+/// panic on some malformed input rather than returning an error, and a bad
+/// document from a remote mustn't take the application down.
+pub(crate) fn untrusted<T, F>(decode: F) -> Result<T, String>
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    panic::catch_unwind(AssertUnwindSafe(decode)).unwrap_or_else(|_| Err("The document is corrupt".to_string()))
+}
+
+/// The changes in a saved document from somewhere we don't control. It's
+/// loaded on its own first, so whatever is wrong with it can't get as far as
+/// our backend, and refused if we wouldn't be able to read it.
+fn load_untrusted(data: &[u8]) -> Result<Vec<automerge::Change>, String> {
+    untrusted(|| {
+        let remote_backend = automerge::Backend::load(data.to_vec()).map_err(|e| format!("{:?}", e))?;
+        let mut remote_frontend = automerge::Frontend::new();
+        remote_frontend
+            .apply_patch(remote_backend.get_patch().map_err(|e| format!("{:?}", e))?)
+            .map_err(|e| format!("{:?}", e))?;
+        schema::check(remote_frontend.state()).map_err(|e| e.to_string())?;
+        automerge::Change::load_document(data).map_err(|e| format!("{:?}", e))
+    })
+}
+
+/// Decodes one change, e.g. one streamed by live sync
+pub fn decode_change(bytes: Vec<u8>) -> Result<automerge::Change, String> {
+    untrusted(|| automerge::Change::from_bytes(bytes).map_err(|e| format!("{:?}", e)))
+}
+
+fn item_value(item: &Item) -> Value {
+    let mut value = hashmap! {
+        "value" => Value::Primitive(item.task.as_str().into()),
+        "completed" => Value::Primitive(item.done.into()),
+        "id" => Value::Primitive(item.id.as_str().into()),
+    };
+    // Left out rather than null when unset, so items look the same as
+    // ever to clients which don't know about them
+    if let Some(recurrence) = item.recurrence.as_ref() {
+        value.insert("recurrence", recurrence_value(recurrence));
+    }
+    if let Some(due) = item.due {
+        value.insert("due", due_value(due));
+    }
+    if let Some(at) = item.deleted_at {
+        value.insert("deletedAt", Value::Primitive(ScalarValue::Int(at)));
+    }
+    value.into()
+}
+
+fn recurrence_value(recurrence: &Recurrence) -> Value {
+    Value::Primitive(recurrence.to_string().as_str().into())
+}
+
+fn due_value(due: NaiveDate) -> Value {
+    Value::Primitive(due.format(DUE_FORMAT).to_string().as_str().into())
+}
+
+/// Sets the fields of the item at `index` which differ between `old` and `new`
+fn set_fields(doc: &mut dyn MutableDocument, index: u32, old: &Item, new: &Item) -> Result<(), InvalidChangeRequest> {
+    let todo = || Path::root().key("todos").index(index);
+    if old.task != new.task {
+        doc.add_change(LocalChange::set(todo().key("value"), Value::Primitive(new.task.as_str().into())))?;
+    }
+    if old.done != new.done {
+        doc.add_change(LocalChange::set(todo().key("completed"), Value::Primitive(new.done.into())))?;
+    }
+    if old.recurrence != new.recurrence {
+        set_optional(doc, index, "recurrence", new.recurrence.as_ref().map(recurrence_value))?;
+    }
+    if old.due != new.due {
+        set_optional(doc, index, "due", new.due.map(due_value))?;
+    }
+    if old.deleted_at != new.deleted_at {
+        set_optional(doc, index, "deletedAt", new.deleted_at.map(|at| Value::Primitive(ScalarValue::Int(at))))?;
+    }
+    Ok(())
+}
+
+/// Folds `other` into `kept`, two copies of the successor of `predecessor`
+fn merge_copies(kept: &mut Item, other: &Item, predecessor: &Item) {
+    if kept.task == predecessor.task && other.task != predecessor.task {
+        kept.task = other.task.clone();
+    }
+    if kept.recurrence == predecessor.recurrence && other.recurrence != predecessor.recurrence {
+        kept.recurrence = other.recurrence.clone();
+    }
+    kept.done |= other.done;
+    kept.due = kept.due.max(other.due);
+    kept.deleted_at = match (kept.deleted_at, other.deleted_at) {
+        (Some(kept), Some(other)) => Some(kept.min(other)),
+        (kept, other) => kept.or(other),
+    };
+}
+
+/// Sets the key `key` of the item at `index` to `value`, or deletes it
+fn set_optional(doc: &mut dyn MutableDocument, index: u32, key: &str, value: Option<Value>) -> Result<(), InvalidChangeRequest> {
+    let path = Path::root().key("todos").index(index).key(key);
+    match value {
+        Some(value) => doc.add_change(LocalChange::set(path, value)),
+        None if todo_at(doc, index).map_or(false, |values| values.contains_key(key)) => doc.add_change(LocalChange::delete(path)),
+        None => Ok(()),
+    }
+}
+
+fn todo_at(doc: &dyn MutableDocument, index: u32) -> Option<HashMap<String, Value>> {
+    match doc.value_at_path(&Path::root().key("todos").index(index)) {
+        Some(Value::Map(values, automerge::MapType::Map)) => Some(values),
+        _ => None,
+    }
+}
+
+/// A well formed replacement for the malformed entry at `index`, keeping as
+/// much of the original as possible
+fn repaired(doc: &dyn MutableDocument, index: u32) -> Value {
+    let values = todo_at(doc, index).unwrap_or_default();
+    let task = match values.get("value") {
+        Some(Value::Primitive(ScalarValue::Str(task))) => task.clone(),
+        Some(Value::Primitive(other)) => format!("{:?}", other),
+        _ => String::new(),
+    };
+    let done = match values.get("completed") {
+        Some(Value::Primitive(ScalarValue::Boolean(done))) => *done,
+        _ => false,
+    };
+    let id = match values.get("id") {
+        Some(Value::Primitive(ScalarValue::Str(id))) => id.clone(),
+        _ => uuid::Uuid::new_v4().to_string(),
+    };
+    hashmap! {
+        "value" => Value::Primitive(task.as_str().into()),
+        "completed" => Value::Primitive(done.into()),
+        "id" => Value::Primitive(id.as_str().into()),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    /// A document with a good item, then a malformed one, then another
+    /// malformed one
+    fn with_problems() -> Document {
+        let mut document = Document::default();
+        document.add(&Item::new("good"));
+        document
+            .change(None, |doc| {
+                let bad = |value: Value, id: &str| -> Value {
+                    hashmap! {
+                        "value" => value,
+                        "completed" => Value::Primitive("yes".into()),
+                        "id" => Value::Primitive(id.into()),
+                    }
+                    .into()
+                };
+                doc.add_change(LocalChange::insert(Path::root().key("todos").index(1), bad(Value::Primitive(ScalarValue::Int(5)), "five")))?;
+                doc.add_change(LocalChange::insert(Path::root().key("todos").index(2), bad(Value::Primitive("gone".into()), "gone")))
+            })
+            .unwrap();
+        document
+    }
+
+    #[test]
+    fn recover_replays_the_journal() {
+        let base = std::env::temp_dir().join(format!("todomvc-recover-{}", uuid::Uuid::new_v4().simple()));
+        let mut documents = Document::recover_free(&base).unwrap();
+        assert_eq!(documents.len(), 1);
+        let mut document = documents.remove(0);
+        assert!(document.is_autosaved());
+        let dir = document.journal.as_ref().unwrap().dir().to_path_buf();
+        document.set_signer(Arc::new(Signer::from_secret(&[1; 32]).unwrap()));
+        for task in &["one", "two", "three"] {
+            document.add(&Item::new(*task));
+        }
+        let id = document.items()[0].id.clone();
+        document.toggle(&id);
+        let items = format!("{:?}", document.items());
+        let signatures = document.signatures().clone();
+        assert_eq!(signatures.len(), 4);
+
+        // A second instance gets a list of its own
+        let other = Document::recover_free(&base).unwrap();
+        assert_eq!(other.len(), 1);
+        assert_ne!(other[0].journal.as_ref().unwrap().dir(), dir.as_path());
+        drop(other);
+
+        // Crash without compacting, part way through journaling a change
+        drop(document);
+        let mut journal = std::fs::OpenOptions::new().append(true).open(dir.join("journal")).unwrap();
+        std::io::Write::write_all(&mut journal, &[9, 0, 0, 0, 1]).unwrap();
+        drop(journal);
+
+        // Both lists come back
+        let recovered = Document::recover_free(&base).unwrap();
+        assert_eq!(recovered.len(), 2);
+        let mut recovered = recovered
+            .into_iter()
+            .find(|document| document.journal.as_ref().unwrap().dir() == dir.as_path())
+            .unwrap();
+        assert_eq!(format!("{:?}", recovered.items()), items);
+        assert_eq!(recovered.signatures(), &signatures);
+
+        // A discarded list is gone for good
+        recovered.discard_autosave().unwrap();
+        assert!(!recovered.is_autosaved());
+        assert!(!dir.exists());
+        assert_eq!(Document::recover_free(&base).unwrap().len(), 1);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn merges_copies_of_a_successor_keeping_edits() {
+        let today = NaiveDate::from_ymd(2021, 3, 1);
+        let mut recurring = Item::new("Stand-up");
+        recurring.recurrence = Some("daily".parse().unwrap());
+        let shared = Item::new("Same id");
+        let mut alice = Document::default();
+        alice.add(&recurring);
+        let mut bob = Document::load(alice.save().unwrap()).unwrap();
+        alice.toggle_all_on(&[recurring.id.clone()], today);
+        bob.toggle_all_on(&[recurring.id.clone()], today);
+        let successor = successor_id(&recurring.id);
+        bob.rename(&successor, "Stand-up, in the big room");
+        // Only copies of a successor are merged
+        alice.add(&shared);
+        bob.add(&shared);
+        alice.merge(&bob.save().unwrap()).unwrap();
+        bob.merge(&alice.save().unwrap()).unwrap();
+        for document in &[alice, bob] {
+            let items = document.items();
+            let copies: Vec<&str> = items.iter().filter(|item| item.id == successor).map(|item| item.task.as_str()).collect();
+            assert_eq!(copies, vec!["Stand-up, in the big room"]);
+            assert_eq!(items.iter().filter(|item| item.id == shared.id).count(), 2);
+        }
+    }
+
+    #[test]
+    fn repairs_and_deletes_problems() {
+        let mut document = with_problems();
+        let problems = document.items().problems().to_vec();
+        assert_eq!(problems.iter().map(|p| p.index).collect::<Vec<_>>(), vec![Some(1), Some(2)]);
+
+        // Nothing happens if the entry has changed since it was shown
+        document.fix_problem(1, Some("stale".to_string()), true);
+        assert_eq!(document.items().problems().len(), 2);
+
+        document.fix_problem(1, problems[0].object_id.clone(), true);
+        let items = document.items();
+        assert_eq!(items.problems().len(), 1);
+        let repaired = items.iter().find(|item| item.id == "five").unwrap();
+        assert_eq!((repaired.task.as_str(), repaired.done), ("Int(5)", false));
+
+        document.fix_problem(2, problems[1].object_id.clone(), false);
+        let items = document.items();
+        assert!(items.problems().is_empty());
+        assert_eq!(items.iter().map(|item| item.task.as_str()).collect::<Vec<_>>(), vec!["good", "Int(5)"]);
+    }
+
+    #[derive(Clone, Debug)]
+    enum Edit {
+        Add(String),
+        Toggle(usize),
+        Rename(usize, String),
+        Trash(usize),
+        EmptyTrash,
+        /// Bends the entry out of shape, so it becomes a problem
+        Break(usize),
+        /// Merges the other peer's document
+        Merge,
+    }
+
+    fn edit() -> impl Strategy<Value = (bool, Edit)> {
+        let edit = prop_oneof![
+            "[a-z]{0,8}".prop_map(Edit::Add),
+            any::<usize>().prop_map(Edit::Toggle),
+            (any::<usize>(), "[a-z]{0,8}").prop_map(|(index, task)| Edit::Rename(index, task)),
+            any::<usize>().prop_map(Edit::Trash),
+            Just(Edit::EmptyTrash),
+            any::<usize>().prop_map(Edit::Break),
+            Just(Edit::Merge),
+        ];
+        (any::<bool>(), edit)
+    }
+
+    fn id_at(document: &Document, index: usize) -> Option<String> {
+        let items = document.items();
+        items.get(index % items.len().max(1)).map(|item| item.id.clone())
+    }
+
+    fn apply(document: &mut Document, other: &Document, edit: Edit) {
+        match edit {
+            Edit::Add(task) => document.add(&Item::new(task)),
+            Edit::Toggle(index) => {
+                if let Some(id) = id_at(document, index) {
+                    document.toggle(&id);
+                }
+            }
+            Edit::Rename(index, task) => {
+                if let Some(id) = id_at(document, index) {
+                    document.rename(&id, &task);
+                }
+            }
+            Edit::Trash(index) => {
+                if let Some(id) = id_at(document, index) {
+                    document.remove(&id);
+                }
+            }
+            Edit::EmptyTrash => document.empty_trash(),
+            Edit::Break(index) if !document.entries.is_empty() => {
+                let index = (index % document.entries.len()) as u32;
+                document
+                    .change(None, |doc| {
+                        doc.add_change(LocalChange::set(
+                            Path::root().key("todos").index(index).key("value"),
+                            Value::Primitive(ScalarValue::Int(1)),
+                        ))
+                    })
+                    .unwrap();
+            }
+            Edit::Break(_) => {}
+            Edit::Merge => document.merge(&other.save().unwrap()).unwrap(),
+        }
+    }
+
+    /// The entries kept up to date from patches, against decoding the
+    /// whole document again
+    fn assert_matches_rebuild(document: &mut Document) {
+        let incremental = format!("{:?}", document.entries);
+        document.rebuild();
+        assert_eq!(incremental, format!("{:?}", document.entries));
+        let walked: HashSet<String> = document.backend.get_changes(&[]).into_iter().map(signing::hash_of).collect();
+        assert_eq!(*document.change_hashes(), walked);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn patched_entries_match_a_rebuild(edits in proptest::collection::vec(edit(), 1..30)) {
+            let mut alice = Document::default();
+            let mut bob = Document::load(alice.save().unwrap()).unwrap();
+            for (on_bob, edit) in edits {
+                if on_bob {
+                    apply(&mut bob, &alice, edit);
+                    assert_matches_rebuild(&mut bob);
+                } else {
+                    apply(&mut alice, &bob, edit);
+                    assert_matches_rebuild(&mut alice);
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,143 @@
+use std::str::FromStr;
 
+use automerge_protocol as amp;
 
+/// The change which creates the empty `todos` list every todo document starts
+/// from. Every peer applies the exact same bytes so that everyone's `todos`
+/// refers to the same list object rather than to concurrently created ones.
+///
+/// This was generated with actor `INIT_ACTOR` at `INIT_TIME`, see
+/// `InitChangeBuilder::todos` for the code which reproduces it.
 pub const INIT_CHANGE: [u8; 76] = [
     133,111,74,131,85,127,230,135,1,66,0,16,26,65,79,136,58,15,72,82,150,89,129,47,104,213,119,57,
     1,1,146,154,199,128,6,14,73,110,105,116,105,97,108,105,122,97,116,105,111,110,0,5,13,7,28,1,
     34,2,46,2,56,2,127,5,116,111,100,111,115,1,127,2,127,0,127,0
 ];
 
+/// The actor which authored `INIT_CHANGE`
+pub const INIT_ACTOR: &str = "1a414f883a0f48529659812f68d57739";
+/// The timestamp (in seconds) recorded in `INIT_CHANGE`
+pub const INIT_TIME: i64 = 1_611_779_346;
+/// The message recorded in `INIT_CHANGE`
+pub const INIT_MESSAGE: &str = "Initialization";
+
+/// The shape of a value created by an initial change
+#[derive(Clone, Debug)]
+pub enum Field {
+    List,
+    Map(Vec<(String, Field)>),
+    Scalar(amp::ScalarValue),
+}
+
+/// Deterministically builds the initial change of a document from an actor
+/// id, a timestamp and a schema.
+///
+/// Peers which build their initial change with the same inputs will produce
+/// byte for byte identical changes, and therefore share the objects that
+/// change creates.
+#[derive(Clone, Debug)]
+pub struct InitChangeBuilder {
+    actor: amp::ActorID,
+    time: i64,
+    message: Option<String>,
+    fields: Vec<(String, Field)>,
+}
+
+impl InitChangeBuilder {
+    pub fn new(actor: amp::ActorID) -> Self {
+        InitChangeBuilder {
+            actor,
+            time: 0,
+            message: None,
+            fields: Vec::new(),
+        }
+    }
+
+    /// The builder which reproduces `INIT_CHANGE`
+    pub fn todos() -> Self {
+        InitChangeBuilder::new(amp::ActorID::from_str(INIT_ACTOR).unwrap())
+            .time(INIT_TIME)
+            .message(INIT_MESSAGE)
+            .field("todos", Field::List)
+    }
+
+    pub fn time(mut self, time: i64) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn message<S: Into<String>>(mut self, message: S) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Adds a key to the root map. Keys are written in the order they are
+    /// added.
+    pub fn field<S: Into<String>>(mut self, key: S, field: Field) -> Self {
+        self.fields.push((key.into(), field));
+        self
+    }
+
+    pub fn build(&self) -> automerge::Change {
+        let mut operations = Vec::new();
+        self.push_fields(amp::ObjectID::Root, &self.fields, &mut operations);
+        let change = amp::UncompressedChange {
+            operations,
+            actor_id: self.actor.clone(),
+            hash: None,
+            seq: 1,
+            start_op: 1,
+            time: self.time,
+            message: self.message.clone(),
+            deps: Vec::new(),
+            extra_bytes: Vec::new(),
+        };
+        automerge::Change::from(&change)
+    }
+
+    fn push_fields(&self, obj: amp::ObjectID, fields: &[(String, Field)], ops: &mut Vec<amp::Op>) {
+        for (key, field) in fields {
+            let op_id = amp::OpID(ops.len() as u64 + 1, self.actor.clone());
+            let action = match field {
+                Field::List => amp::OpType::Make(amp::ObjType::Sequence(amp::SequenceType::List)),
+                Field::Map(_) => amp::OpType::Make(amp::ObjType::Map(amp::MapType::Map)),
+                Field::Scalar(value) => amp::OpType::Set(value.clone()),
+            };
+            ops.push(amp::Op {
+                action,
+                obj: obj.clone(),
+                key: amp::Key::Map(key.clone()),
+                pred: Vec::new(),
+                insert: false,
+            });
+            if let Field::Map(children) = field {
+                self.push_fields(amp::ObjectID::Id(op_id), children, ops);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn todos_builder_reproduces_init_change() {
+        let change = InitChangeBuilder::todos().build();
+        assert_eq!(change.raw_bytes(), &INIT_CHANGE[..]);
+    }
+
+    #[test]
+    fn builder_is_deterministic() {
+        let build = || {
+            InitChangeBuilder::new(amp::ActorID::from_str("0000").unwrap())
+                .field("todos", Field::List)
+                .field("archive", Field::List)
+                .field("settings", Field::Map(vec![
+                    ("title".to_string(), Field::Scalar(amp::ScalarValue::Str("Chores".to_string()))),
+                ]))
+                .build()
+        };
+        assert_eq!(build().raw_bytes(), build().raw_bytes());
+    }
+}
@@ -1,24 +1,32 @@
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 
 use vgtk::lib::gio::{File, FileCreateFlags, FileExt, OutputStreamExt};
 use vgtk::lib::glib::{Bytes, Error, FileError};
-use vgtk::lib::gtk::*;
-
-use vgtk::{gtk, VNode};
 
 use serde_derive::{Deserialize, Serialize};
 
 use automerge::{ScalarValue, Value};
+use chrono::NaiveDate;
 
-use crate::app::{Model, Msg};
+use crate::recurrence::Recurrence;
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct Item {
     pub task: String,
     pub id: String,
     pub done: bool,
+    /// Brings the task back as a new item when it's completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
+    /// The day the task is due, or for a recurring task, the day this
+    /// occurrence is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<NaiveDate>,
+    /// When it was moved to the trash, in milliseconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
 }
 
 impl Item {
@@ -27,28 +35,85 @@ impl Item {
             task: label.into(),
             id: uuid::Uuid::new_v4().to_string(),
             done: false,
+            recurrence: None,
+            due: None,
+            deleted_at: None,
         }
     }
 
-    pub fn render(&self, index: usize) -> VNode<Model> {
-        let label = if self.done {
-            format!(
-                "<span strikethrough=\"true\" alpha=\"50%\">{}</span>",
-                self.task
-            )
-        } else {
-            self.task.clone()
-        };
-        gtk! {
-            <ListBoxRow>
-                <Box spacing=10 orientation=Orientation::Horizontal>
-                    <CheckButton active=self.done on toggled=|_| Msg::Toggle { index } />
-                    <Label label=label use_markup=true Box::fill=true />
-                    <Button Box::pack_type=PackType::End relief=ReliefStyle::None
-                            always_show_image=true image="edit-delete"
-                            on clicked=|_| Msg::Remove { index } />
-                </Box>
-            </ListBoxRow>
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// The next occurrence of a recurring task completed on `today`, due
+    /// on the first day the rule gives after it, or after the day this one
+    /// was due if that's later
+    pub fn successor(&self, today: NaiveDate) -> Option<Item> {
+        let recurrence = self.recurrence.as_ref()?;
+        let after = self.due.map_or(today, |due| due.max(today));
+        Some(Item {
+            task: self.task.clone(),
+            id: crate::recurrence::successor_id(&self.id),
+            done: false,
+            recurrence: Some(recurrence.clone()),
+            due: Some(recurrence.next(after)),
+            deleted_at: None,
+        })
+    }
+}
+
+/// Date format of an item's `due` key
+pub const DUE_FORMAT: &str = "%Y-%m-%d";
+
+/// How many rows of the list are built at a time. The list is built a page
+/// at a time as it's scrolled, see `Items::page`.
+pub const PAGE_SIZE: usize = 200;
+
+/// An entry of the `todos` sequence which could not be decoded into an `Item`
+#[derive(Clone, Debug)]
+pub struct Problem {
+    /// The index in the `todos` sequence, `None` if the whole document is broken
+    pub index: Option<usize>,
+    pub object_id: Option<String>,
+    pub reason: String,
+}
+
+/// One decoded entry of the `todos` sequence
+#[derive(Clone, Debug)]
+pub enum Entry {
+    Item(Item),
+    Problem { object_id: Option<String>, reason: String },
+}
+
+impl Entry {
+    pub fn decode(value: Option<&automerge::Value>, object_id: Option<String>) -> Entry {
+        match value.map(Item::try_from) {
+            Some(Ok(item)) => Entry::Item(item),
+            Some(Err(reason)) => Entry::Problem { object_id, reason },
+            None => Entry::Problem {
+                object_id,
+                reason: "entry has no value".to_string(),
+            },
+        }
+    }
+
+    /// Decodes every entry of the `todos` sequence, failing only if the
+    /// document has no `todos` sequence at all
+    pub fn decode_all<F>(value: &automerge::Value, object_id_at: F) -> Result<Vec<Entry>, String>
+    where
+        F: Fn(usize) -> Option<String>,
+    {
+        match value {
+            automerge::Value::Map(items, automerge::MapType::Map) => match items.get("todos") {
+                Some(automerge::Value::Sequence(elems)) => Ok(elems
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| Entry::decode(Some(value), object_id_at(index)))
+                    .collect()),
+                Some(_) => Err("todos key did not contain a sequence".to_string()),
+                None => Err("No 'todos' key found".to_string()),
+            },
+            _ => Err("attempted to create items from something which wasn't a map".to_string()),
         }
     }
 }
@@ -56,12 +121,13 @@ impl Item {
 #[derive(Clone, Default, Debug)]
 pub struct Items {
     items: Vec<Item>,
+    problems: Vec<Problem>,
 }
 
 impl Items {
     pub async fn read_from(file: &File) -> Result<Items, Error> {
         serde_json::from_slice(&file.load_contents_async_future().await?.0)
-            .map(|items| Items { items })
+            .map(|items| Items { items, problems: Vec::new() })
             .map_err(|err| {
                 Error::new(
                     FileError::Inval,
@@ -86,6 +152,60 @@ impl Items {
     }
 }
 
+impl Items {
+    /// Decodes the document state, quarantining malformed entries as
+    /// `Problem`s rather than failing. `object_id_at` looks up the object id
+    /// of the entry at an index of `todos`, for diagnostics.
+    pub fn decode<F>(value: &automerge::Value, object_id_at: F) -> Items
+    where
+        F: Fn(usize) -> Option<String>,
+    {
+        match Entry::decode_all(value, object_id_at) {
+            Ok(entries) => Items::from_entries(&entries),
+            Err(reason) => Items::broken(&reason),
+        }
+    }
+
+    pub fn from_entries(entries: &[Entry]) -> Items {
+        let mut items = Items::default();
+        for (index, entry) in entries.iter().enumerate() {
+            match entry {
+                Entry::Item(item) => items.items.push(item.clone()),
+                Entry::Problem { object_id, reason } => items.problems.push(Problem {
+                    index: Some(index),
+                    object_id: object_id.clone(),
+                    reason: reason.clone(),
+                }),
+            }
+        }
+        items
+    }
+
+    pub fn broken(reason: &str) -> Items {
+        Items {
+            items: Vec::new(),
+            problems: vec![Problem {
+                index: None,
+                object_id: None,
+                reason: reason.to_string(),
+            }],
+        }
+    }
+
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// The first `rows` items `matches` keeps, which are the ones with
+    /// rows built after scrolling through `rows / PAGE_SIZE` pages
+    pub fn page<F>(&self, rows: usize, matches: F) -> Vec<Item>
+    where
+        F: Fn(&Item) -> bool,
+    {
+        self.items.iter().filter(|item| matches(item)).take(rows).cloned().collect()
+    }
+}
+
 impl Deref for Items {
     type Target = Vec<Item>;
 
@@ -107,6 +227,7 @@ impl FromIterator<Item> for Items {
     {
         Items {
             items: iter.into_iter().collect(),
+            problems: Vec::new(),
         }
     }
 }
@@ -117,48 +238,151 @@ impl TryFrom<&automerge::Value> for Item {
     fn try_from(value: &automerge::Value) -> Result<Self, Self::Error> {
         match value {
             automerge::Value::Map(values, automerge::MapType::Map) => {
-                let id = values.get("id");
-                let task = values.get("value");
-                let completed = values.get("completed");
-                match (id, task, completed) {
-                    (
-                        Some(Value::Primitive(ScalarValue::Str(id))),
-                        Some(Value::Primitive(ScalarValue::Str(task))),
-                        Some(Value::Primitive(ScalarValue::Boolean(done))),
-                    ) => Ok(Item {
-                        id: id.to_string(),
-                        task: task.to_string(),
-                        done: *done,
-                    }),
-                    _ => Err("invalid value for item".to_string()),
-                }
+                let id = match values.get("id") {
+                    Some(Value::Primitive(ScalarValue::Str(id))) => id,
+                    Some(_) => return Err("'id' is not a string".to_string()),
+                    None => return Err("missing 'id'".to_string()),
+                };
+                let task = match values.get("value") {
+                    Some(Value::Primitive(ScalarValue::Str(task))) => task,
+                    Some(_) => return Err("'value' is not a string".to_string()),
+                    None => return Err("missing 'value'".to_string()),
+                };
+                // react-todomvc treats a missing 'completed' as not completed
+                let done = match values.get("completed") {
+                    Some(Value::Primitive(ScalarValue::Boolean(done))) => *done,
+                    None => false,
+                    Some(_) => return Err("'completed' is not a boolean".to_string()),
+                };
+                // Both only written by this application, so may be missing
+                let recurrence = match values.get("recurrence") {
+                    Some(Value::Primitive(ScalarValue::Str(rule))) => {
+                        Some(rule.parse().map_err(|e| format!("'recurrence' is invalid: {}", e))?)
+                    }
+                    None => None,
+                    Some(_) => return Err("'recurrence' is not a string".to_string()),
+                };
+                let due = match values.get("due") {
+                    Some(Value::Primitive(ScalarValue::Str(due))) => {
+                        Some(NaiveDate::parse_from_str(due, DUE_FORMAT).map_err(|_| format!("'due' is not a date: {}", due))?)
+                    }
+                    None => None,
+                    Some(_) => return Err("'due' is not a string".to_string()),
+                };
+                // Written as a plain number so the JS client can read it too
+                let deleted_at = match values.get("deletedAt") {
+                    Some(Value::Primitive(ScalarValue::Int(at))) => Some(*at),
+                    Some(Value::Primitive(ScalarValue::Uint(at))) => Some(*at as i64),
+                    Some(Value::Primitive(ScalarValue::F64(at))) => Some(*at as i64),
+                    None => None,
+                    Some(_) => return Err("'deletedAt' is not a number".to_string()),
+                };
+                Ok(Item {
+                    id: id.to_string(),
+                    task: task.to_string(),
+                    done,
+                    recurrence,
+                    due,
+                    deleted_at,
+                })
             }
             _ => Err("attempted to create an item from a non-map type".to_string()),
         }
     }
 }
 
+/// Strict decoding, fails on the first malformed entry. Use `Items::decode`
+/// to keep the valid entries.
 impl TryFrom<&automerge::Value> for Items {
     type Error = String;
 
     fn try_from(value: &automerge::Value) -> Result<Self, Self::Error> {
-        match value {
-            automerge::Value::Map(items, automerge::MapType::Map) => {
-                let todos = items.get("todos").ok_or("No 'todos' key found")?;
-                match todos {
-                    automerge::Value::Sequence(elems) => {
-                        let init: Vec<Item> = Vec::new();
-                        let items = elems.iter().try_fold(init, |mut items, value| -> Result<Vec<Item>, String> {
-                            let item: Item = value.try_into()?;
-                            items.push(item);
-                            Ok(items)
-                        })?;
-                        Ok(Items{items})
-                    },
-                    _ => Err("todos key did not contain a sequence".to_string())
-                }
+        let items = Items::decode(value, |_| None);
+        match items.problems.first() {
+            Some(problem) => Err(problem.reason.clone()),
+            None => Ok(items),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use automerge::MapType;
+
+    fn map(fields: Vec<(&str, Value)>) -> Value {
+        Value::Map(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect(), MapType::Map)
+    }
+
+    fn string(s: &str) -> Value {
+        Value::Primitive(ScalarValue::Str(s.to_string()))
+    }
+
+    fn todo(extra: Vec<(&str, Value)>) -> Value {
+        let mut fields = vec![("id", string("a")), ("value", string("Buy milk")), ("completed", Value::Primitive(ScalarValue::Boolean(false)))];
+        for (key, value) in extra {
+            fields.retain(|(k, _)| *k != key);
+            fields.push((key, value));
+        }
+        map(fields)
+    }
+
+    fn without(key: &str) -> Value {
+        match todo(vec![]) {
+            Value::Map(mut values, kind) => {
+                values.remove(key);
+                Value::Map(values, kind)
             }
-            _ => Err("attempted to create items from something which wasn't a map".to_string())
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn malformed_items_say_what_is_wrong() {
+        let int = |n| Value::Primitive(ScalarValue::Int(n));
+        let cases = vec![
+            (string("Buy milk"), "non-map"),
+            (without("id"), "missing 'id'"),
+            (todo(vec![("id", int(1))]), "'id' is not a string"),
+            (without("value"), "missing 'value'"),
+            (todo(vec![("value", int(1))]), "'value' is not a string"),
+            (todo(vec![("completed", string("yes"))]), "'completed' is not a boolean"),
+            (todo(vec![("recurrence", string("yearly"))]), "'recurrence' is invalid"),
+            (todo(vec![("recurrence", int(1))]), "'recurrence' is not a string"),
+            (todo(vec![("due", string("tomorrow"))]), "'due' is not a date"),
+            (todo(vec![("due", int(1))]), "'due' is not a string"),
+            (todo(vec![("deletedAt", string("yesterday"))]), "'deletedAt' is not a number"),
+        ];
+        for (value, reason) in cases {
+            match Item::try_from(&value) {
+                Err(e) => assert!(e.contains(reason), "{:?} gave {:?}, not {:?}", value, e, reason),
+                Ok(item) => panic!("{:?} decoded to {:?}", value, item),
+            }
+        }
+        // 'completed' may be left out, as the JS client does
+        assert!(!Item::try_from(&without("completed")).unwrap().done);
+    }
+
+    #[test]
+    fn decode_quarantines_malformed_entries() {
+        let root = map(vec![(
+            "todos",
+            Value::Sequence(vec![todo(vec![]), todo(vec![("value", Value::Primitive(ScalarValue::Null))]), todo(vec![("id", string("b"))])]),
+        )]);
+        let items = Items::decode(&root, |index| Some(format!("object {}", index)));
+        let ids: Vec<&str> = items.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(items.problems().len(), 1);
+        assert_eq!(items.problems()[0].index, Some(1));
+        assert_eq!(items.problems()[0].object_id.as_deref(), Some("object 1"));
+        assert!(Items::try_from(&root).is_err());
+
+        for broken in vec![map(vec![]), map(vec![("todos", string("none"))]), string("root")] {
+            let items = Items::decode(&broken, |_| None);
+            assert!(items.is_empty());
+            assert_eq!(items.problems().len(), 1);
+            assert_eq!(items.problems()[0].index, None);
         }
     }
 }
@@ -0,0 +1,302 @@
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+
+const SNAPSHOT: &str = "snapshot.automerge";
+const JOURNAL: &str = "journal";
+const SIGNATURES: &str = "signatures";
+/// Held locked by whichever instance has the journal open
+const LOCK: &str = "lock";
+
+/// Fold the journal into the snapshot once it holds this many changes
+pub const COMPACT_EVERY: usize = 100;
+
+/// A crash safe record of a document on disk.
+///
+/// The document lives in two files in `dir`: `snapshot.automerge`, a full
+/// `Backend::save`, and `journal`, the encoded changes made since that
+/// snapshot. Each journal record is a little endian `u32` length followed by
+/// that many bytes of change, and is synced to disk before `append` returns.
+/// A record torn by a crash is dropped when the journal is next read.
+/// Signatures of those changes are recorded the same way in `signatures`,
+/// so neither log is rewritten until the next snapshot.
+///
+/// Only one instance may have a journal open at a time, so a `lock` file in
+/// `dir` is kept locked for as long as it's open.
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    file: File,
+    signatures: File,
+    len: usize,
+    _lock: File,
+}
+
+/// What was on disk when a journal was opened
+#[derive(Debug, Default, PartialEq)]
+pub struct Recovered {
+    /// The last snapshot, if there is one
+    pub snapshot: Option<Vec<u8>>,
+    /// Every intact change recorded since
+    pub changes: Vec<Vec<u8>>,
+    /// Every intact signature record appended since
+    pub signatures: Vec<Vec<u8>>,
+}
+
+impl Journal {
+    /// Where the application keeps its autosaves, under the XDG data
+    /// directory. Each list is autosaved in a directory of its own in there.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("todomvc").join("autosave"))
+    }
+
+    /// Opens the journal in `dir`, returning what it holds. Fails with
+    /// `io::ErrorKind::WouldBlock` if another instance has it open.
+    pub fn open(dir: &Path) -> io::Result<(Journal, Recovered)> {
+        fs::create_dir_all(dir)?;
+        let lock = OpenOptions::new().write(true).create(true).open(dir.join(LOCK))?;
+        lock.try_lock_exclusive().map_err(|e| match e.kind() {
+            io::ErrorKind::WouldBlock => {
+                io::Error::new(io::ErrorKind::WouldBlock, format!("{} is open in another instance", dir.display()))
+            }
+            _ => e,
+        })?;
+        let snapshot = match fs::read(dir.join(SNAPSHOT)) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let (file, changes) = open_log(&dir.join(JOURNAL))?;
+        let (signatures_file, signatures) = open_log(&dir.join(SIGNATURES))?;
+        let journal = Journal {
+            dir: dir.to_path_buf(),
+            file,
+            signatures: signatures_file,
+            len: changes.len(),
+            _lock: lock,
+        };
+        let recovered = Recovered {
+            snapshot,
+            changes,
+            signatures,
+        };
+        Ok((journal, recovered))
+    }
+
+    /// The directory the journal is kept in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The number of changes recorded since the last snapshot
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, change: &[u8]) -> io::Result<()> {
+        append_record(&mut self.file, change)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Records the signatures of changes made or received since the last
+    /// snapshot, however the caller encodes them
+    pub fn append_signatures(&mut self, signatures: &[u8]) -> io::Result<()> {
+        append_record(&mut self.signatures, signatures)
+    }
+
+    /// Replaces the snapshot with `snapshot` and empties the journal and the
+    /// signature log, whose contents the caller has to have saved first.
+    ///
+    /// The new snapshot is written beside the old one and renamed over it,
+    /// so a crash leaves either the old snapshot and full journal or the new
+    /// snapshot. Replaying journal changes the snapshot already contains is
+    /// harmless.
+    pub fn compact(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", SNAPSHOT));
+        {
+            let mut out = File::create(&tmp)?;
+            out.write_all(snapshot)?;
+            out.sync_all()?;
+        }
+        fs::rename(&tmp, self.dir.join(SNAPSHOT))?;
+        File::open(&self.dir)?.sync_all()?;
+        for log in [&self.file, &self.signatures].iter() {
+            log.set_len(0)?;
+            log.sync_all()?;
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// The directory of every list autosaved under `base`. An autosave from
+/// before each list had its own directory is moved into one first.
+pub fn lists(base: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(base)?;
+    if base.join(JOURNAL).exists() || base.join(SNAPSHOT).exists() {
+        let dir = new_list(base);
+        fs::create_dir(&dir)?;
+        for entry in fs::read_dir(base)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::rename(entry.path(), dir.join(entry.file_name()))?;
+            }
+        }
+    }
+    let mut lists = Vec::new();
+    for entry in fs::read_dir(base)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            lists.push(entry.path());
+        }
+    }
+    lists.sort();
+    Ok(lists)
+}
+
+/// A directory to autosave a new list in, under `base`
+pub fn new_list(base: &Path) -> PathBuf {
+    base.join(uuid::Uuid::new_v4().simple().to_string())
+}
+
+/// Opens a log of records for appending, returning it and the intact
+/// records it holds
+fn open_log(path: &Path) -> io::Result<(File, Vec<Vec<u8>>)> {
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let (records, intact) = read_records(&data);
+    if intact < data.len() {
+        // Drop the torn record so new ones are appended after good data
+        file.set_len(intact as u64)?;
+        file.sync_all()?;
+    }
+    Ok((file, records))
+}
+
+fn append_record(file: &mut File, data: &[u8]) -> io::Result<()> {
+    let mut record = Vec::with_capacity(data.len() + 4);
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(data);
+    file.write_all(&record)?;
+    file.sync_data()
+}
+
+/// Splits journal data into records, returning them and the length of the
+/// data they cover
+fn read_records(data: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let start = offset + 4;
+        if start + len > data.len() {
+            break;
+        }
+        records.push(data[start..start + len].to_vec());
+        offset = start + len;
+    }
+    (records, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("todomvc-{}-{}", name, uuid::Uuid::new_v4().simple()))
+    }
+
+    #[test]
+    fn appended_changes_survive_a_reopen() {
+        let dir = temp_dir("journal");
+        let (mut journal, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered, Recovered::default());
+        journal.append(b"one").unwrap();
+        journal.append(b"two").unwrap();
+        drop(journal);
+
+        let (journal, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered.changes, vec![b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(journal.len(), 2);
+        drop(journal);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_torn_record_is_dropped_and_written_over() {
+        let dir = temp_dir("journal");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        journal.append(b"intact").unwrap();
+        drop(journal);
+        // A crash part way through appending a 100 byte record
+        let intact = fs::metadata(dir.join(JOURNAL)).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(dir.join(JOURNAL)).unwrap();
+        file.write_all(&100u32.to_le_bytes()).unwrap();
+        file.write_all(b"tor").unwrap();
+        drop(file);
+
+        let (mut journal, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered.changes, vec![b"intact".to_vec()]);
+        assert_eq!(fs::metadata(dir.join(JOURNAL)).unwrap().len(), intact);
+        journal.append(b"after").unwrap();
+        drop(journal);
+
+        let (journal, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered.changes, vec![b"intact".to_vec(), b"after".to_vec()]);
+        drop(journal);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compact_replaces_the_snapshot_and_empties_the_journal() {
+        let dir = temp_dir("journal");
+        let (mut journal, _) = Journal::open(&dir).unwrap();
+        journal.append(b"folded in").unwrap();
+        journal.append_signatures(b"saved elsewhere").unwrap();
+        journal.compact(b"snapshot").unwrap();
+        assert!(journal.is_empty());
+        journal.append(b"since").unwrap();
+        journal.append_signatures(b"signed since").unwrap();
+        drop(journal);
+
+        let (journal, recovered) = Journal::open(&dir).unwrap();
+        assert_eq!(recovered.snapshot, Some(b"snapshot".to_vec()));
+        assert_eq!(recovered.changes, vec![b"since".to_vec()]);
+        assert_eq!(recovered.signatures, vec![b"signed since".to_vec()]);
+        drop(journal);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_one_instance_opens_a_journal() {
+        let dir = temp_dir("journal");
+        let (journal, _) = Journal::open(&dir).unwrap();
+        assert_eq!(Journal::open(&dir).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        drop(journal);
+        assert!(Journal::open(&dir).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_old_autosave_gets_a_directory_of_its_own() {
+        let base = temp_dir("autosave");
+        fs::create_dir_all(&base).unwrap();
+        fs::write(base.join(SNAPSHOT), b"snapshot").unwrap();
+        fs::write(base.join(JOURNAL), b"").unwrap();
+        let lists = lists(&base).unwrap();
+        assert_eq!(lists.len(), 1);
+        let (_, recovered) = Journal::open(&lists[0]).unwrap();
+        assert_eq!(recovered.snapshot, Some(b"snapshot".to_vec()));
+        assert!(!base.join(SNAPSHOT).exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+pub mod compact;
+pub mod config;
+pub mod crypto;
+pub mod dbus;
+pub mod document;
+pub mod init_change;
+pub mod items;
+pub mod journal;
+pub mod live;
+pub mod outbox;
+pub mod p2p;
+pub mod preview;
+pub mod recurrence;
+pub mod reminders;
+pub mod remote;
+pub mod schema;
+pub mod shortcuts;
+pub mod signing;
+pub mod status;
+
+pub use init_change::INIT_CHANGE;
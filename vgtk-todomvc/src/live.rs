@@ -0,0 +1,154 @@
+use std::io;
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc::UnboundedSender;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::HeaderValue;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::{Message, WebSocket};
+
+/// How long to wait before trying to reconnect a dropped connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How often to check for local changes to send while waiting for the server
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub enum LiveEvent {
+    Connected,
+    /// An encoded change from the server, to apply with `Document::apply_changes`.
+    /// Our own changes come back too, applying them again does nothing.
+    Change(Vec<u8>),
+    /// The connection dropped, we'll keep trying to reconnect
+    Disconnected(String),
+    /// The server closed the connection because it won't let us in, e.g. we
+    /// sent a change to a document we may only read. We've stopped trying.
+    Refused(String),
+}
+
+/// The WebSocket URL on `server/server.py` which streams the changes to the
+/// document at an `http://` remote URL
+pub fn live_url(remote: &str) -> Option<String> {
+    let url = reqwest::Url::parse(remote).ok()?;
+    let scheme = match url.scheme() {
+        "http" => "ws",
+        _ => return None,
+    };
+    Some(format!(
+        "{}://{}:{}/live{}",
+        scheme,
+        url.host_str()?,
+        url.port_or_known_default()?,
+        url.path()
+    ))
+}
+
+/// Streams changes between a document and everyone else subscribed to the
+/// same document on the server, on a background thread.
+///
+/// `history` is every change the document holds and `local` receives each
+/// local change as it's made. Every time the connection is (re)established
+/// we send the server everything we have, which it ignores if it's seen it
+/// before, and the server sends us every change it's received since the last
+/// one we saw. So nothing is lost if the connection drops.
+///
+/// `authorization` is sent as the `Authorization` header, if there is one.
+/// Events are sent to `events` tagged with `url`. The thread stops when the
+/// document drops `local` or nobody is listening to `events` any more.
+pub fn connect(
+    url: String,
+    authorization: Option<String>,
+    history: Vec<Vec<u8>>,
+    local: Receiver<Vec<u8>>,
+    events: UnboundedSender<(String, LiveEvent)>,
+) {
+    thread::spawn(move || {
+        let mut ours = history;
+        // How many of the server's changes for this document we've seen
+        let mut seen = 0;
+        loop {
+            let result = open(&url, authorization.as_deref(), seen).and_then(|socket| {
+                let _ = events.unbounded_send((url.clone(), LiveEvent::Connected));
+                run(socket, &url, &mut ours, &mut seen, &local, &events)
+            });
+            let reason = match result {
+                Ok(Stop) => return,
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    let _ = events.unbounded_send((url.clone(), LiveEvent::Refused(e.to_string())));
+                    return;
+                }
+                Err(e) => e.to_string(),
+            };
+            if events.unbounded_send((url.clone(), LiveEvent::Disconnected(reason))).is_err() {
+                return;
+            }
+            thread::sleep(RECONNECT_DELAY);
+        }
+    });
+}
+
+/// The document was closed, stop syncing it
+struct Stop;
+
+fn open(url: &str, authorization: Option<&str>, seen: usize) -> Result<WebSocket<TcpStream>, io::Error> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let url = format!("{}?since={}", url, seen);
+    let parsed = reqwest::Url::parse(&url).map_err(|e| invalid(e.to_string()))?;
+    let host = parsed.host_str().unwrap_or("localhost");
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let mut request = url.as_str().into_client_request().map_err(|e| invalid(e.to_string()))?;
+    if let Some(authorization) = authorization {
+        let value = HeaderValue::from_str(authorization).map_err(|e| invalid(e.to_string()))?;
+        request.headers_mut().insert("Authorization", value);
+    }
+    let stream = TcpStream::connect((host, port))?;
+    let (socket, _) = tungstenite::client(request, stream)
+        .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string()))?;
+    // Time out reads so we get to send local changes while nothing arrives
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+fn run(
+    mut socket: WebSocket<TcpStream>,
+    url: &str,
+    ours: &mut Vec<Vec<u8>>,
+    seen: &mut usize,
+    local: &Receiver<Vec<u8>>,
+    events: &UnboundedSender<(String, LiveEvent)>,
+) -> Result<Stop, io::Error> {
+    let to_io = |e: tungstenite::Error| io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string());
+    for change in ours.iter() {
+        socket.write_message(Message::Binary(change.clone())).map_err(to_io)?;
+    }
+    loop {
+        loop {
+            match local.try_recv() {
+                Ok(change) => {
+                    socket.write_message(Message::Binary(change.clone())).map_err(to_io)?;
+                    ours.push(change);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(Stop),
+            }
+        }
+        match socket.read_message() {
+            Ok(Message::Binary(change)) => {
+                *seen += 1;
+                if events.unbounded_send((url.to_string(), LiveEvent::Change(change))).is_err() {
+                    return Ok(Stop);
+                }
+            }
+            // Reconnecting would only be refused again
+            Ok(Message::Close(Some(frame))) if frame.code == CloseCode::Policy => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, frame.reason.to_string()));
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(to_io(e)),
+        }
+    }
+}
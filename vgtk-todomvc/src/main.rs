@@ -2,10 +2,9 @@
 
 mod about;
 mod app;
-mod items;
+mod cli;
 mod radio;
-mod init_change;
-use init_change::INIT_CHANGE;
+mod rows;
 
 use vgtk::run;
 
@@ -13,5 +12,9 @@ use app::Model;
 
 fn main() {
     pretty_env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(status) = cli::run(&args) {
+        std::process::exit(status);
+    }
     std::process::exit(run::<Model>());
 }
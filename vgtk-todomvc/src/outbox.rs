@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Wait this long before the first retry, doubling each time after that
+const FIRST_RETRY: i64 = 30;
+/// Never wait longer than this between retries
+const MAX_RETRY: i64 = 60 * 60;
+
+/// A push to a remote which hasn't succeeded yet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Pending {
+    /// When the first push failed, in seconds since the epoch
+    pub since: i64,
+    /// How many times we've tried
+    pub attempts: u32,
+    /// When to try again, in seconds since the epoch
+    pub next_attempt: i64,
+    pub last_error: String,
+    /// Whether the remote encrypts what's pushed to it, in which case it
+    /// can't be retried until it's added again with its passphrase
+    #[serde(default)]
+    pub encrypted: bool,
+    /// The autosave directory of the list to push, which stays the same
+    /// across restarts. `None` for a list which isn't autosaved.
+    #[serde(default)]
+    pub list: Option<PathBuf>,
+}
+
+impl Pending {
+    /// Whether this is a push of the list autosaved in `list`
+    pub fn is_for(&self, list: Option<&Path>) -> bool {
+        self.list.as_deref() == list
+    }
+}
+
+/// The remotes we still owe a push, kept on disk so they're retried after a
+/// restart too.
+///
+/// A push sends the whole document, so there's at most one pending push per
+/// remote: whenever it's retried it sends the document as it is then. That
+/// is the document of the list which last failed to push to it, since its
+/// push would have replaced the other's anyway.
+#[derive(Debug)]
+pub struct Outbox {
+    path: Option<PathBuf>,
+    pending: BTreeMap<String, Pending>,
+}
+
+impl Outbox {
+    /// Where the application keeps its outbox, under the XDG data directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("todomvc").join("outbox.json"))
+    }
+
+    /// Opens the outbox at `path`, an empty one if there's nothing there
+    pub fn open(path: &Path) -> Result<Outbox, String> {
+        let pending = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(Outbox {
+            path: Some(path.to_path_buf()),
+            pending,
+        })
+    }
+
+    /// An outbox which isn't saved anywhere
+    pub fn in_memory() -> Outbox {
+        Outbox {
+            path: None,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Every pending push, by the location of its remote
+    pub fn pending(&self) -> &BTreeMap<String, Pending> {
+        &self.pending
+    }
+
+    pub fn get(&self, location: &str) -> Option<&Pending> {
+        self.pending.get(location)
+    }
+
+    /// The remotes whose pushes are due to be retried at `now`
+    pub fn due(&self, now: i64) -> Vec<String> {
+        self.pending
+            .iter()
+            .filter(|(_, pending)| pending.next_attempt <= now)
+            .map(|(location, _)| location.clone())
+            .collect()
+    }
+
+    /// Records that pushing the list autosaved in `list` to `location`
+    /// failed at `now`, and schedules the next try with exponential backoff
+    pub fn failed(&mut self, location: &str, list: Option<&Path>, encrypted: bool, now: i64, error: &str) -> Result<(), String> {
+        if self.pending.get(location).map_or(false, |pending| !pending.is_for(list)) {
+            self.pending.remove(location);
+        }
+        let pending = self.pending.entry(location.to_string()).or_insert(Pending {
+            since: now,
+            attempts: 0,
+            next_attempt: now,
+            last_error: String::new(),
+            encrypted,
+            list: list.map(Path::to_path_buf),
+        });
+        pending.attempts += 1;
+        let delay = FIRST_RETRY.saturating_mul(1 << (pending.attempts - 1).min(16)).min(MAX_RETRY);
+        pending.next_attempt = now + delay;
+        pending.last_error = error.to_string();
+        self.save()
+    }
+
+    /// Records that pushing the list autosaved in `list` to `location`
+    /// succeeded. A push of another list to it is left waiting.
+    pub fn succeeded(&mut self, location: &str, list: Option<&Path>) -> Result<(), String> {
+        if self.pending.get(location).map_or(false, |pending| pending.is_for(list)) {
+            self.pending.remove(location);
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let data = serde_json::to_vec_pretty(&self.pending).map_err(|e| e.to_string())?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&tmp, &data))
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_up_to_an_hour() {
+        let mut outbox = Outbox::in_memory();
+        let mut delays = Vec::new();
+        for _ in 0..10 {
+            outbox.failed("http://localhost:5000/list", None, false, 1000, "offline").unwrap();
+            delays.push(outbox.get("http://localhost:5000/list").unwrap().next_attempt - 1000);
+        }
+        assert_eq!(delays, vec![30, 60, 120, 240, 480, 960, 1920, 3600, 3600, 3600]);
+        assert_eq!(outbox.due(1000 + 3599), Vec::<String>::new());
+        assert_eq!(outbox.due(1000 + 3600), vec!["http://localhost:5000/list".to_string()]);
+        outbox.succeeded("http://localhost:5000/list", None).unwrap();
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_list_which_last_failed() {
+        let mut outbox = Outbox::in_memory();
+        let (shopping, chores) = (Path::new("/autosave/shopping"), Path::new("/autosave/chores"));
+        outbox.failed("http://localhost:5000/list", Some(shopping), false, 1000, "offline").unwrap();
+        outbox.failed("http://localhost:5000/list", Some(shopping), false, 1030, "offline").unwrap();
+        outbox.failed("http://localhost:5000/list", Some(chores), false, 1100, "offline").unwrap();
+        let pending = outbox.get("http://localhost:5000/list").unwrap();
+        assert!(pending.is_for(Some(chores)));
+        assert_eq!((pending.since, pending.attempts), (1100, 1));
+
+        // Another list pushing successfully doesn't take it out
+        outbox.succeeded("http://localhost:5000/list", Some(shopping)).unwrap();
+        assert!(!outbox.is_empty());
+        outbox.succeeded("http://localhost:5000/list", Some(chores)).unwrap();
+        assert!(outbox.is_empty());
+    }
+}
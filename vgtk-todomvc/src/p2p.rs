@@ -0,0 +1,271 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc::UnboundedSender;
+
+use crate::document::Document;
+use crate::live::LiveEvent;
+
+/// The first frame each side sends, so we don't sync with something which
+/// isn't another instance of the application
+const HELLO: &[u8] = b"todomvc-p2p/2";
+/// Refuse frames bigger than this rather than trying to allocate them
+const MAX_FRAME: usize = 64 * 1024 * 1024;
+/// How long to wait before trying to reconnect to a peer we lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Writes a frame: a little endian `u32` length followed by that many bytes
+pub fn write_frame(stream: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(data.len() + 4);
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(data);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads a frame written with `write_frame`
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too big", len)));
+    }
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// A new code for peers to give when they connect, to be passed on to them
+/// some other way. Twelve hex digits are short enough to read out and too
+/// many to guess.
+pub fn pairing_code() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..12].to_string()
+}
+
+/// Accepts connections from other instances on `port`, 0 for any free
+/// port, and syncs `document` with each of them which gives `code` until
+/// they disconnect. Returns the address we're listening on.
+///
+/// We listen on every interface, so that instances elsewhere on the LAN can
+/// connect, which is why peers have to know the code.
+///
+/// Events for each peer are sent to `events` tagged with its address.
+pub fn share(
+    port: u16,
+    code: String,
+    document: Arc<Mutex<Document>>,
+    events: UnboundedSender<(String, LiveEvent)>,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let address = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let code = code.clone();
+            let document = document.clone();
+            let events = events.clone();
+            thread::spawn(move || {
+                let name = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                let result = accept(&stream, &code).and_then(|_| sync(stream, &name, &document, &events));
+                // Someone who doesn't know the code never shows up as a peer
+                if let Err(e) = &result {
+                    if e.kind() == io::ErrorKind::PermissionDenied {
+                        return;
+                    }
+                }
+                let _ = events.unbounded_send((name, LiveEvent::Disconnected(describe(result))));
+            });
+        }
+    });
+    Ok(address)
+}
+
+/// Connects to an instance sharing on `address`, a `host:port`, gives it
+/// `code` and syncs `document` with it, reconnecting whenever the
+/// connection drops. If it refuses the code we stop trying.
+///
+/// Events are sent to `events` tagged with `address`.
+pub fn connect(
+    address: String,
+    code: String,
+    document: Arc<Mutex<Document>>,
+    events: UnboundedSender<(String, LiveEvent)>,
+) {
+    thread::spawn(move || loop {
+        let result = TcpStream::connect(address.as_str())
+            .and_then(|stream| introduce(&stream, &code).map(|_| stream))
+            .and_then(|stream| sync(stream, &address, &document, &events));
+        let event = match result {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => LiveEvent::Refused(e.to_string()),
+            result => LiveEvent::Disconnected(describe(result)),
+        };
+        let refused = matches!(event, LiveEvent::Refused(_));
+        if events.unbounded_send((address.clone(), event)).is_err() || refused {
+            return;
+        }
+        thread::sleep(RECONNECT_DELAY);
+    });
+}
+
+/// The connecting side of the handshake: we send `HELLO` and the code, and
+/// the side sharing answers with `HELLO` if the code is right or hangs up
+fn introduce(mut stream: &TcpStream, code: &str) -> io::Result<()> {
+    write_frame(&mut stream, HELLO)?;
+    write_frame(&mut stream, code.as_bytes())?;
+    match read_frame(&mut stream) {
+        Ok(hello) if hello == HELLO => Ok(()),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "not a todomvc peer")),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "the peer refused the pairing code"))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The sharing side of the handshake. Nothing of the document is sent
+/// until the other side has given the right code.
+fn accept(mut stream: &TcpStream, code: &str) -> io::Result<()> {
+    if read_frame(&mut stream)? != HELLO {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a todomvc peer"));
+    }
+    let given = read_frame(&mut stream)?;
+    // Compare every byte, so the time taken doesn't give away how much of
+    // the code was right
+    let matches = given.len() == code.len() && given.iter().zip(code.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0;
+    if !matches {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "wrong pairing code"));
+    }
+    write_frame(&mut stream, HELLO)
+}
+
+fn describe(result: io::Result<()>) -> String {
+    match result {
+        Ok(()) => "connection closed".to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Syncs over a connection which has been through the handshake until it
+/// drops.
+///
+/// Each side sends every change it has, then each local change as it's
+/// made, one change per frame. Changes the other side already has
+/// are ignored when they're applied, so there's no need to work out which
+/// ones it's missing first.
+fn sync(
+    mut stream: TcpStream,
+    name: &str,
+    document: &Mutex<Document>,
+    events: &UnboundedSender<(String, LiveEvent)>,
+) -> io::Result<()> {
+    let (history, local) = {
+        let mut document = document.lock().unwrap();
+        (document.all_changes(), document.subscribe_local_changes())
+    };
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        let sent = history
+            .iter()
+            .try_for_each(|change| write_frame(&mut writer, change))
+            .and_then(|_| local.iter().try_for_each(|change| write_frame(&mut writer, &change)));
+        if sent.is_err() {
+            let _ = writer.shutdown(Shutdown::Both);
+        }
+    });
+    let result = receive(&mut stream, name, events);
+    // Stops the writer the next time it has something to send
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn receive(stream: &mut TcpStream, name: &str, events: &UnboundedSender<(String, LiveEvent)>) -> io::Result<()> {
+    let _ = events.unbounded_send((name.to_string(), LiveEvent::Connected));
+    loop {
+        let change = match read_frame(stream) {
+            Ok(change) => change,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if events.unbounded_send((name.to_string(), LiveEvent::Change(change))).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use crate::items::Item;
+
+    /// Applies changes from `events` to `document` until it holds `count` items
+    fn receive_items(document: &Mutex<Document>, events: &mut UnboundedReceiver<(String, LiveEvent)>, count: usize) {
+        while document.lock().unwrap().items().len() < count {
+            match block_on(events.next()) {
+                Some((_, LiveEvent::Change(bytes))) => {
+                    let change = automerge::Change::from_bytes(bytes).unwrap();
+                    document.lock().unwrap().apply_changes(vec![change]).unwrap();
+                }
+                Some((_, LiveEvent::Disconnected(reason))) | Some((_, LiveEvent::Refused(reason))) => {
+                    panic!("disconnected: {}", reason)
+                }
+                Some((_, LiveEvent::Connected)) => {}
+                None => panic!("no more events"),
+            }
+        }
+    }
+
+    #[test]
+    fn two_instances_sync_on_localhost() {
+        let sharing = Arc::new(Mutex::new(Document::default()));
+        let connecting = Arc::new(Mutex::new(Document::default()));
+        sharing.lock().unwrap().add(&Item::new("shared before connecting".to_string()));
+
+        let (sharing_events, mut sharing_received) = unbounded();
+        let (connecting_events, mut connecting_received) = unbounded();
+        let code = pairing_code();
+        let address = share(0, code.clone(), sharing.clone(), sharing_events).unwrap();
+        connect(format!("127.0.0.1:{}", address.port()), code, connecting.clone(), connecting_events);
+        receive_items(&connecting, &mut connecting_received, 1);
+
+        connecting.lock().unwrap().add(&Item::new("added after connecting".to_string()));
+        receive_items(&sharing, &mut sharing_received, 2);
+
+        let ids = |document: &Mutex<Document>| -> Vec<String> {
+            document.lock().unwrap().items().iter().map(|item| item.id.clone()).collect()
+        };
+        assert_eq!(ids(&sharing), ids(&connecting));
+    }
+
+    #[test]
+    fn a_peer_without_the_code_gets_nothing() {
+        let sharing = Arc::new(Mutex::new(Document::default()));
+        let connecting = Arc::new(Mutex::new(Document::default()));
+        sharing.lock().unwrap().add(&Item::new("not for strangers".to_string()));
+
+        let (sharing_events, mut sharing_received) = unbounded();
+        let (connecting_events, mut connecting_received) = unbounded();
+        let address = share(0, pairing_code(), sharing, sharing_events).unwrap();
+        connect(format!("127.0.0.1:{}", address.port()), pairing_code(), connecting.clone(), connecting_events);
+
+        match block_on(connecting_received.next()) {
+            Some((_, LiveEvent::Refused(_))) => {}
+            other => panic!("expected to be refused, got {:?}", other),
+        }
+        // We gave up rather than trying again
+        assert!(block_on(connecting_received.next()).is_none());
+        assert!(connecting.lock().unwrap().items().is_empty());
+        assert!(sharing_received.try_next().is_err());
+    }
+}
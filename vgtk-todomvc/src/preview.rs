@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::document::Document;
+use crate::items::{Item, Items};
+use crate::signing::{Flagged, Keyring, Signatures};
+
+/// How one item would differ after a pull
+#[derive(Clone, Debug)]
+pub enum ItemChange {
+    Added(Item),
+    Removed(Item),
+    Completed(Item),
+    Reopened(Item),
+    Renamed { from: String, item: Item },
+}
+
+impl fmt::Display for ItemChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemChange::Added(item) => write!(f, "+ {}", item.task),
+            ItemChange::Removed(item) => write!(f, "- {}", item.task),
+            ItemChange::Completed(item) => write!(f, "✓ {}", item.task),
+            ItemChange::Reopened(item) => write!(f, "○ {}", item.task),
+            ItemChange::Renamed { from, item } => write!(f, "~ {} → {}", from, item.task),
+        }
+    }
+}
+
+/// What merging a pull would do
+#[derive(Clone, Debug, Default)]
+pub struct Preview {
+    pub changes: Vec<ItemChange>,
+    /// Changes which wouldn't be merged, or would be with a warning
+    pub flagged: Vec<Flagged>,
+}
+
+/// The differences between two versions of a list, by item id, in the
+/// order the items appear. Items moved to the trash count as removed, and
+/// ones restored from it as added.
+pub fn diff(before: &Items, after: &Items) -> Vec<ItemChange> {
+    let old: HashMap<&str, &Item> = before.iter().filter(|item| !item.is_deleted()).map(|item| (item.id.as_str(), item)).collect();
+    let new: HashMap<&str, &Item> = after.iter().filter(|item| !item.is_deleted()).map(|item| (item.id.as_str(), item)).collect();
+    let mut changes = Vec::new();
+    for item in after.iter().filter(|item| !item.is_deleted()) {
+        match old.get(item.id.as_str()) {
+            None => changes.push(ItemChange::Added(item.clone())),
+            Some(old) => {
+                if old.task != item.task {
+                    changes.push(ItemChange::Renamed {
+                        from: old.task.clone(),
+                        item: item.clone(),
+                    });
+                }
+                if old.done != item.done {
+                    changes.push(if item.done {
+                        ItemChange::Completed(item.clone())
+                    } else {
+                        ItemChange::Reopened(item.clone())
+                    });
+                }
+            }
+        }
+    }
+    for item in before.iter().filter(|item| !item.is_deleted() && !new.contains_key(item.id.as_str())) {
+        changes.push(ItemChange::Removed(item.clone()));
+    }
+    changes
+}
+
+/// Merges copies of a document pulled from a remote into a scratch copy of
+/// `document` and reports what that would change, leaving `document` alone
+pub fn preview(document: &Document, copies: &[Vec<u8>], signatures: &Signatures, keyring: &Keyring) -> Result<Preview, String> {
+    let mut scratch = document.fork()?;
+    let mut flagged = Vec::new();
+    for data in copies {
+        flagged.extend(scratch.merge_verified(data, signatures, keyring)?);
+    }
+    Ok(Preview {
+        changes: diff(&document.items(), &scratch.items()),
+        flagged,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tasks(document: &Document) -> Vec<String> {
+        document.items().iter().map(|item| format!("{:?}", item)).collect()
+    }
+
+    #[test]
+    fn previews_every_kind_of_change_without_merging() {
+        let mut local = Document::default();
+        for task in &["trashed", "reopened", "completed", "renamed"] {
+            local.add(&Item::new(*task));
+        }
+        let ids: Vec<String> = local.items().iter().map(|item| item.id.clone()).collect();
+        local.toggle(&ids[1]);
+
+        let mut remote = Document::load(local.save().unwrap()).unwrap();
+        remote.remove(&ids[0]);
+        remote.toggle(&ids[1]);
+        remote.toggle(&ids[2]);
+        remote.rename(&ids[3], "was renamed");
+        remote.add(&Item::new("added"));
+
+        let before = (tasks(&local), local.change_hashes());
+        let preview = preview(&local, &[remote.save().unwrap()], remote.signatures(), &Keyring::default()).unwrap();
+        let changes: Vec<String> = preview.changes.iter().map(ItemChange::to_string).collect();
+        assert_eq!(changes, vec!["○ reopened", "✓ completed", "~ renamed → was renamed", "+ added", "- trashed"]);
+        // The remote's changes are unsigned, which is worth a warning only
+        assert!(!preview.flagged.is_empty());
+        assert!(preview.flagged.iter().all(|flag| !flag.rejected));
+        assert_eq!((tasks(&local), local.change_hashes()), before);
+    }
+
+    #[test]
+    fn restoring_from_the_trash_counts_as_adding() {
+        let mut before = Document::default();
+        before.add(&Item::new("back again"));
+        let id = before.items()[0].id.clone();
+        before.remove(&id);
+        let mut after = Document::load(before.save().unwrap()).unwrap();
+        after.restore(&id);
+        let changes: Vec<String> = diff(&before.items(), &after.items()).iter().map(ItemChange::to_string).collect();
+        assert_eq!(changes, vec!["+ back again"]);
+        assert!(diff(&after.items(), &after.items()).is_empty());
+    }
+}
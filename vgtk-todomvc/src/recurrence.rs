@@ -0,0 +1,174 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// How often a task comes back once it's done. Stored in the document as
+/// a string, `daily`, `weekly:mon,thu` or `monthly`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Recurrence {
+    Daily,
+    /// On each of the days, which are never empty, in order from Monday
+    Weekly(Vec<Weekday>),
+    /// On the same day of the month, or the last day of months which are
+    /// too short
+    Monthly,
+}
+
+impl Recurrence {
+    /// The first date after `after` the task is due again
+    pub fn next(&self, after: NaiveDate) -> NaiveDate {
+        match self {
+            Recurrence::Daily => after + Duration::days(1),
+            Recurrence::Weekly(days) => (1..=7)
+                .map(|n| after + Duration::days(n))
+                .find(|date| days.contains(&date.weekday()))
+                .unwrap_or_else(|| after + Duration::days(7)),
+            Recurrence::Monthly => {
+                let (year, month) = if after.month() == 12 { (after.year() + 1, 1) } else { (after.year(), after.month() + 1) };
+                (1..=after.day())
+                    .rev()
+                    .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                    .unwrap()
+            }
+        }
+    }
+
+    /// How it's shown next to the task, e.g. "weekly on Mon, Thu"
+    pub fn describe(&self) -> String {
+        match self {
+            Recurrence::Daily => "daily".to_string(),
+            Recurrence::Weekly(days) => {
+                let days: Vec<String> = days.iter().map(|day| format!("{:?}", day)).collect();
+                format!("weekly on {}", days.join(", "))
+            }
+            Recurrence::Monthly => "monthly".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recurrence::Daily => write!(f, "daily"),
+            Recurrence::Weekly(days) => {
+                let days: Vec<String> = days.iter().map(|day| format!("{:?}", day).to_lowercase()).collect();
+                write!(f, "weekly:{}", days.join(","))
+            }
+            Recurrence::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+/// Reads the form it's stored in, and the form `describe` shows it in, so
+/// "weekly on mon, thu" can be typed in as well as "weekly:mon,thu"
+impl FromStr for Recurrence {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let normalised = rule.trim().to_lowercase();
+        let (kind, days) = match normalised.find(|c| c == ':' || c == ' ') {
+            Some(split) => {
+                let days = normalised[split + 1..].trim();
+                (&normalised[..split], Some(days.strip_prefix("on ").unwrap_or(days)))
+            }
+            None => (normalised.as_str(), None),
+        };
+        match (kind, days) {
+            ("daily", None) => Ok(Recurrence::Daily),
+            ("monthly", None) => Ok(Recurrence::Monthly),
+            ("weekly", Some(days)) => {
+                let mut days = days
+                    .split(',')
+                    .map(|day| day.trim().parse::<Weekday>().map_err(|_| format!("\"{}\" is not a day of the week", day.trim())))
+                    .collect::<Result<Vec<Weekday>, String>>()?;
+                days.sort_by_key(Weekday::num_days_from_monday);
+                days.dedup();
+                Ok(Recurrence::Weekly(days))
+            }
+            ("weekly", None) => Err("weekly needs the days it's on, e.g. \"weekly on mon, thu\"".to_string()),
+            _ => Err(format!("\"{}\" isn't daily, weekly on some days or monthly", rule.trim())),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(recurrence: Recurrence) -> String {
+        recurrence.to_string()
+    }
+}
+
+/// The id of the item which follows the one with id `id` when it's
+/// completed. It's the same wherever it's worked out, so peers which
+/// complete the same item at the same time spawn the same successor, and
+/// the copies are merged into one.
+pub fn successor_id(id: &str) -> String {
+    uuid::Uuid::new_v5(&uuid::NAMESPACE_OID, &format!("todomvc:next:{}", id)).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn finds_the_next_occurrence() {
+        let weekly: Recurrence = "weekly on Thu, mon".parse().unwrap();
+        assert_eq!(weekly.to_string(), "weekly:mon,thu");
+        assert_eq!(weekly, "weekly:mon,thu".parse().unwrap());
+        // 2021-03-01 is a Monday
+        assert_eq!(weekly.next(date("2021-03-01")), date("2021-03-04"));
+        assert_eq!(weekly.next(date("2021-03-04")), date("2021-03-08"));
+        assert_eq!(Recurrence::Daily.next(date("2021-02-28")), date("2021-03-01"));
+        assert_eq!(Recurrence::Monthly.next(date("2021-01-31")), date("2021-02-28"));
+        assert_eq!(Recurrence::Monthly.next(date("2021-12-15")), date("2022-01-15"));
+        assert!("weekly".parse::<Recurrence>().is_err());
+        assert!("yearly".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn concurrent_completions_spawn_one_successor() {
+        use crate::document::Document;
+        use crate::items::Item;
+
+        let mut item = Item::new("Stand-up");
+        item.recurrence = Some("weekly on mon, thu".parse().unwrap());
+        let mut alice = Document::default();
+        alice.add(&item);
+        let mut bob = Document::load(alice.save().unwrap()).unwrap();
+        alice.toggle_all_on(&[item.id.clone()], date("2021-03-01"));
+        bob.toggle_all_on(&[item.id.clone()], date("2021-03-01"));
+        // Completing it again mustn't spawn another either
+        bob.toggle_all_on(&[item.id.clone()], date("2021-03-01"));
+        bob.toggle_all_on(&[item.id.clone()], date("2021-03-01"));
+        alice.merge(&bob.save().unwrap()).unwrap();
+        bob.merge(&alice.save().unwrap()).unwrap();
+        for document in &[alice, bob] {
+            let items = document.items();
+            let summary: Vec<(&str, bool, Option<NaiveDate>)> =
+                items.iter().map(|item| (item.id.as_str(), item.done, item.due)).collect();
+            assert_eq!(
+                summary,
+                vec![
+                    (item.id.as_str(), true, None),
+                    (successor_id(&item.id).as_str(), false, Some(date("2021-03-04"))),
+                ]
+            );
+        }
+    }
+}
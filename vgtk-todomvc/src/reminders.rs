@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::items::Item;
+
+/// The hour of its due date a task becomes due, and of the day after, that
+/// it becomes overdue
+pub const DUE_HOUR: u32 = 9;
+
+/// Tells the time, in local time. Reminders take one so tests can run them
+/// on a fake clock.
+pub trait Clock {
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// The real local time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        chrono::Local::now().naive_local()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Due,
+    Overdue,
+}
+
+impl Stage {
+    /// How far a task due on `due` has got at `now`, `None` if it isn't
+    /// due yet
+    pub fn at(due: NaiveDate, now: NaiveDateTime) -> Option<Stage> {
+        let due_at = due.and_time(NaiveTime::from_hms(DUE_HOUR, 0, 0));
+        if now >= due_at + Duration::days(1) {
+            Some(Stage::Overdue)
+        } else if now >= due_at {
+            Some(Stage::Due)
+        } else {
+            None
+        }
+    }
+}
+
+/// A notification to raise about a task
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reminder {
+    pub id: String,
+    pub task: String,
+    pub due: NaiveDate,
+    pub stage: Stage,
+}
+
+impl Reminder {
+    pub fn title(&self) -> String {
+        match self.stage {
+            Stage::Due => format!("Due today: {}", self.task),
+            Stage::Overdue => format!("Overdue: {}", self.task),
+        }
+    }
+}
+
+/// What to do about notifications after a check
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub raise: Vec<Reminder>,
+    /// The ids of items whose notifications are out of date, because
+    /// they've been done, deleted or moved to a later date
+    pub withdraw: Vec<String>,
+}
+
+/// Keeps track of which tasks have been notified about, so each one is
+/// notified once when it becomes due and once more when it becomes overdue,
+/// and of which have been snoozed.
+///
+/// It only looks at the items it's given each time it checks, so tasks
+/// which arrive in a pull are picked up by the next check like any other.
+pub struct Reminders<C> {
+    clock: C,
+    /// The last stage each task was notified at, by item id
+    notified: HashMap<String, Stage>,
+    /// When each snoozed task should be notified about again, by item id
+    snoozed: HashMap<String, NaiveDateTime>,
+}
+
+impl<C: Clock> Reminders<C> {
+    pub fn new(clock: C) -> Reminders<C> {
+        Reminders {
+            clock,
+            notified: HashMap::new(),
+            snoozed: HashMap::new(),
+        }
+    }
+
+    /// Works out which notifications to raise and withdraw now, given every
+    /// item there is
+    pub fn check<'a, I>(&mut self, items: I) -> Changes
+    where
+        I: IntoIterator<Item = &'a Item>,
+    {
+        let now = self.clock.now();
+        let mut changes = Changes::default();
+        let mut current = HashSet::new();
+        for item in items.into_iter().filter(|item| !item.done && !item.is_deleted()) {
+            let (due, stage) = match item.due.and_then(|due| Stage::at(due, now).map(|stage| (due, stage))) {
+                Some(found) => found,
+                None => continue,
+            };
+            current.insert(item.id.clone());
+            match self.snoozed.get(&item.id).copied() {
+                Some(until) if until > now => continue,
+                // Remind again, at whatever stage it's at now
+                Some(_) => {
+                    self.snoozed.remove(&item.id);
+                    self.notified.remove(&item.id);
+                }
+                None => {}
+            }
+            if self.notified.get(&item.id).copied() < Some(stage) {
+                self.notified.insert(item.id.clone(), stage);
+                changes.raise.push(Reminder {
+                    id: item.id.clone(),
+                    task: item.task.clone(),
+                    due,
+                    stage,
+                });
+            }
+        }
+        changes.withdraw = self.notified.keys().filter(|id| !current.contains(*id)).cloned().collect();
+        changes.withdraw.sort();
+        self.notified.retain(|id, _| current.contains(id));
+        self.snoozed.retain(|id, _| current.contains(id));
+        changes
+    }
+
+    /// Holds off reminding about the task with id `id` for `delay`
+    pub fn snooze(&mut self, id: &str, delay: Duration) {
+        let until = self.clock.now() + delay;
+        self.snoozed.insert(id.to_string(), until);
+    }
+
+    /// Holds off reminding about the task with id `id` until `DUE_HOUR`
+    /// tomorrow
+    pub fn snooze_until_tomorrow(&mut self, id: &str) {
+        let tomorrow = self.clock.now().date().succ();
+        self.snoozed.insert(id.to_string(), tomorrow.and_time(NaiveTime::from_hms(DUE_HOUR, 0, 0)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<NaiveDateTime>>);
+
+    impl FakeClock {
+        fn set(&self, time: &str) {
+            self.0.set(NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap());
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> NaiveDateTime {
+            self.0.get()
+        }
+    }
+
+    fn stages(changes: &Changes) -> Vec<Stage> {
+        changes.raise.iter().map(|reminder| reminder.stage).collect()
+    }
+
+    #[test]
+    fn reminds_when_due_overdue_and_snoozed() {
+        let clock = FakeClock(Rc::new(Cell::new(NaiveDateTime::from_timestamp(0, 0))));
+        let mut reminders = Reminders::new(clock.clone());
+        let mut item = Item::new("Water the plants");
+        item.due = Some(NaiveDate::from_ymd(2021, 3, 4));
+
+        clock.set("2021-03-04 08:59");
+        assert_eq!(reminders.check(&[item.clone()]), Changes::default());
+        clock.set("2021-03-04 09:00");
+        assert_eq!(stages(&reminders.check(&[item.clone()])), vec![Stage::Due]);
+        clock.set("2021-03-04 09:01");
+        assert_eq!(reminders.check(&[item.clone()]), Changes::default());
+
+        reminders.snooze(&item.id, Duration::hours(1));
+        clock.set("2021-03-04 09:59");
+        assert_eq!(reminders.check(&[item.clone()]), Changes::default());
+        clock.set("2021-03-04 10:01");
+        assert_eq!(stages(&reminders.check(&[item.clone()])), vec![Stage::Due]);
+
+        clock.set("2021-03-05 09:00");
+        assert_eq!(stages(&reminders.check(&[item.clone()])), vec![Stage::Overdue]);
+        reminders.snooze_until_tomorrow(&item.id);
+        clock.set("2021-03-05 23:00");
+        assert_eq!(reminders.check(&[item.clone()]), Changes::default());
+        clock.set("2021-03-06 09:00");
+        assert_eq!(stages(&reminders.check(&[item.clone()])), vec![Stage::Overdue]);
+
+        item.done = true;
+        let changes = reminders.check(&[item.clone()]);
+        assert_eq!(changes.raise, vec![]);
+        assert_eq!(changes.withdraw, vec![item.id.clone()]);
+    }
+}
@@ -0,0 +1,439 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::config::Credentials;
+use crate::crypto;
+use crate::document::Document;
+use crate::signing::{self, Flagged, Keyring, Signatures};
+
+/// How documents are moved to and from a remote
+pub trait Transport: Debug + Send + Sync {
+    /// Fetches every copy of the document the remote holds
+    fn pull(&self) -> Result<Vec<Vec<u8>>, String>;
+
+    /// Stores our copy of the document. `peer` names this installation, for
+    /// remotes which keep a copy per peer.
+    fn push(&self, peer: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Fetches the signatures stored alongside each copy of the document
+    fn pull_signatures(&self) -> Result<Vec<Vec<u8>>, String>;
+
+    /// Stores the signatures of our copy alongside it
+    fn push_signatures(&self, peer: &str, data: &[u8]) -> Result<(), String>;
+}
+
+/// The signatures stored alongside a document at `path`
+fn signatures_path(path: &Path) -> PathBuf {
+    let mut signatures = path.as_os_str().to_owned();
+    signatures.push(".sig");
+    PathBuf::from(signatures)
+}
+
+/// A document at a URL, fetched with GET and stored with POST, as served by
+/// `server/server.py`
+#[derive(Debug)]
+pub struct HttpTransport {
+    url: reqwest::Url,
+    credentials: Option<Credentials>,
+}
+
+impl HttpTransport {
+    /// Where the signatures are stored, the document's URL plus `.sig`
+    fn signatures_url(&self) -> reqwest::Url {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}.sig", self.url.path()));
+        url
+    }
+
+    fn request(&self, method: reqwest::Method, url: reqwest::Url) -> reqwest::blocking::RequestBuilder {
+        let request = reqwest::blocking::Client::new().request(method, url);
+        match &self.credentials {
+            Some(credentials) => request.header(reqwest::header::AUTHORIZATION, credentials.authorization()),
+            None => request,
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn pull(&self) -> Result<Vec<Vec<u8>>, String> {
+        let response = self
+            .request(reqwest::Method::GET, self.url.clone())
+            .send()
+            .map_err(|e| e.to_string())?;
+        // Nobody has pushed it yet
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let bytes = response
+            .error_for_status()
+            .and_then(|r| r.bytes())
+            .map_err(|e| e.to_string())?;
+        Ok(vec![bytes.to_vec()])
+    }
+
+    fn push(&self, _peer: &str, data: &[u8]) -> Result<(), String> {
+        self.request(reqwest::Method::POST, self.url.clone())
+            .body(data.to_vec())
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn pull_signatures(&self) -> Result<Vec<Vec<u8>>, String> {
+        let response = self
+            .request(reqwest::Method::GET, self.signatures_url())
+            .send()
+            .map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let bytes = response
+            .error_for_status()
+            .and_then(|r| r.bytes())
+            .map_err(|e| e.to_string())?;
+        Ok(vec![bytes.to_vec()])
+    }
+
+    fn push_signatures(&self, _peer: &str, data: &[u8]) -> Result<(), String> {
+        self.request(reqwest::Method::POST, self.signatures_url())
+            .body(data.to_vec())
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// A single document file, shared by everyone who pushes to it. Pulling a
+/// file which doesn't exist yet finds nothing.
+#[derive(Debug)]
+pub struct FileTransport {
+    path: PathBuf,
+}
+
+impl Transport for FileTransport {
+    fn pull(&self) -> Result<Vec<Vec<u8>>, String> {
+        read_if_exists(&self.path)
+    }
+
+    fn push(&self, _peer: &str, data: &[u8]) -> Result<(), String> {
+        write_atomically(&self.path, data)
+    }
+
+    fn pull_signatures(&self) -> Result<Vec<Vec<u8>>, String> {
+        read_if_exists(&signatures_path(&self.path))
+    }
+
+    fn push_signatures(&self, _peer: &str, data: &[u8]) -> Result<(), String> {
+        write_atomically(&signatures_path(&self.path), data)
+    }
+}
+
+/// The file at `path`, or nothing if it doesn't exist yet
+fn read_if_exists(path: &Path) -> Result<Vec<Vec<u8>>, String> {
+    match fs::read(path) {
+        Ok(data) => Ok(vec![data]),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// A shared directory, e.g. synced with Syncthing or mounted over NFS. Each
+/// peer writes only its own `<peer>.automerge` file, so peers never
+/// overwrite each other's changes, and pulling merges every file.
+#[derive(Debug)]
+pub struct DirectoryTransport {
+    dir: PathBuf,
+}
+
+impl DirectoryTransport {
+    /// Reads every file in the directory with the extension `extension`
+    fn read_all(&self, extension: &str) -> Result<Vec<Vec<u8>>, String> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        let mut documents = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().map_or(false, |ext| ext == extension) {
+                documents.push(fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?);
+            }
+        }
+        Ok(documents)
+    }
+
+    fn document_path(&self, peer: &str) -> PathBuf {
+        self.dir.join(format!("{}.automerge", peer))
+    }
+}
+
+impl Transport for DirectoryTransport {
+    fn pull(&self) -> Result<Vec<Vec<u8>>, String> {
+        self.read_all("automerge")
+    }
+
+    fn push(&self, peer: &str, data: &[u8]) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        write_atomically(&self.document_path(peer), data)
+    }
+
+    fn pull_signatures(&self) -> Result<Vec<Vec<u8>>, String> {
+        self.read_all("sig")
+    }
+
+    fn push_signatures(&self, peer: &str, data: &[u8]) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("{}: {}", self.dir.display(), e))?;
+        write_atomically(&signatures_path(&self.document_path(peer)), data)
+    }
+}
+
+/// Wraps another transport so that the remote only ever holds documents
+/// encrypted with a passphrase, which has to be shared out of band
+pub struct EncryptedTransport {
+    inner: Arc<dyn Transport>,
+    passphrase: String,
+}
+
+// Keep the passphrase out of logs
+impl Debug for EncryptedTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncryptedTransport({:?})", self.inner)
+    }
+}
+
+impl Transport for EncryptedTransport {
+    fn pull(&self) -> Result<Vec<Vec<u8>>, String> {
+        self.inner
+            .pull()?
+            .iter()
+            .map(|data| crypto::decrypt(&self.passphrase, data))
+            .collect()
+    }
+
+    fn push(&self, peer: &str, data: &[u8]) -> Result<(), String> {
+        self.inner.push(peer, &crypto::encrypt(&self.passphrase, data)?)
+    }
+
+    fn pull_signatures(&self) -> Result<Vec<Vec<u8>>, String> {
+        self.inner
+            .pull_signatures()?
+            .iter()
+            .map(|data| crypto::decrypt(&self.passphrase, data))
+            .collect()
+    }
+
+    fn push_signatures(&self, peer: &str, data: &[u8]) -> Result<(), String> {
+        self.inner.push_signatures(peer, &crypto::encrypt(&self.passphrase, data)?)
+    }
+}
+
+/// Writes beside the target and renames over it, so that whatever syncs the
+/// file never sees it half written
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Picks the transport for a remote's location:
+///
+/// * `http://` and `https://` URLs use `HttpTransport`, sending
+///   `credentials` if there are any
+/// * `file://` URLs and plain paths use `DirectoryTransport` if they name a
+///   directory, or end in `/`, and `FileTransport` otherwise
+pub fn transport_for(location: &str, credentials: Option<Credentials>) -> Result<Arc<dyn Transport>, String> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let url = reqwest::Url::from_str(location).map_err(|e| format!("{}: {}", location, e))?;
+        return Ok(Arc::new(HttpTransport { url, credentials }));
+    }
+    let path = if location.starts_with("file://") {
+        reqwest::Url::from_str(location)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| format!("{}: not a local file URL", location))?
+    } else if Path::new(location).is_absolute() {
+        PathBuf::from(location)
+    } else {
+        return Err(format!("{}: expected an http(s):// or file:// URL or an absolute path", location));
+    };
+    if location.ends_with('/') || path.is_dir() {
+        Ok(Arc::new(DirectoryTransport { dir: path }))
+    } else {
+        Ok(Arc::new(FileTransport { path }))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Remote {
+    pub location: String,
+    credentials: Option<Credentials>,
+    encrypted: bool,
+    transport: Arc<dyn Transport>,
+}
+
+impl Remote {
+    /// A remote at `location`, which encrypts everything it stores there if
+    /// there's a `passphrase`
+    pub fn new(location: String, credentials: Option<Credentials>, passphrase: Option<String>) -> Result<Remote, String> {
+        let mut transport = transport_for(&location, credentials.clone())?;
+        let encrypted = passphrase.is_some();
+        if let Some(passphrase) = passphrase {
+            transport = Arc::new(EncryptedTransport {
+                inner: transport,
+                passphrase,
+            });
+        }
+        Ok(Remote {
+            location,
+            credentials,
+            encrypted,
+            transport,
+        })
+    }
+
+    /// Whether the remote only holds encrypted documents
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// The `Authorization` header to send the remote, if it needs one
+    pub fn authorization(&self) -> Option<String> {
+        self.credentials.as_ref().map(Credentials::authorization)
+    }
+
+    /// Fetches every copy of the document the remote holds, for
+    /// `Document::merge`
+    pub fn fetch(&self) -> Result<Vec<Vec<u8>>, String> {
+        let copies = self.transport.pull()?;
+        if !self.encrypted && copies.iter().any(|data| crypto::is_encrypted(data)) {
+            return Err("The document is encrypted, add the remote again with its passphrase".to_string());
+        }
+        Ok(copies)
+    }
+
+    /// Stores a saved document on the remote
+    pub fn push(&self, peer: &str, data: &[u8]) -> Result<(), String> {
+        self.transport.push(peer, data)
+    }
+
+    /// Fetches the signatures stored alongside every copy of the document,
+    /// for `Document::merge_verified`
+    pub fn fetch_signatures(&self) -> Result<Signatures, String> {
+        let mut all = Signatures::new();
+        for data in self.transport.pull_signatures()? {
+            all.extend(signing::parse_signatures(&data)?);
+        }
+        Ok(all)
+    }
+
+    /// Stores the signatures of a document alongside it
+    pub fn push_signatures(&self, peer: &str, signatures: &Signatures) -> Result<(), String> {
+        self.transport.push_signatures(peer, &signing::encode_signatures(signatures))
+    }
+
+    /// Merges what the remote holds into `document`, then stores it and its
+    /// signatures there. A push replaces what an HTTP or file remote holds,
+    /// so this way it never loses changes someone else pushed which we
+    /// hadn't pulled. A push landing between our fetch and our store still
+    /// does, the server has no way to turn away a stale one. Returns the
+    /// changes flagged while merging, as `Document::merge_verified` does.
+    pub fn push_document(&self, peer: &str, document: &mut Document, keyring: &Keyring) -> Result<Vec<Flagged>, String> {
+        let copies = self.fetch()?;
+        let signatures = self.fetch_signatures()?;
+        let mut flagged = Vec::new();
+        for data in &copies {
+            flagged.extend(document.merge_verified(data, &signatures, keyring)?);
+        }
+        self.push(peer, &document.save()?)?;
+        self.push_signatures(peer, document.signatures())?;
+        Ok(flagged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("todomvc-remote-{}", uuid::Uuid::new_v4().simple()))
+    }
+
+    #[test]
+    fn picks_the_transport_for_a_location() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("list.automerge");
+        let kind = |location: &str| format!("{:?}", transport_for(location, None).unwrap());
+
+        assert!(kind("http://localhost:5000/list").starts_with("HttpTransport"));
+        assert!(kind("https://example.com/list").starts_with("HttpTransport"));
+        assert!(kind(dir.to_str().unwrap()).starts_with("DirectoryTransport"));
+        assert!(kind(&format!("file://{}", dir.display())).starts_with("DirectoryTransport"));
+        assert!(kind(&format!("{}/", dir.join("not-yet").display())).starts_with("DirectoryTransport"));
+        assert!(kind(file.to_str().unwrap()).starts_with("FileTransport"));
+        assert!(kind(&format!("file://{}", file.display())).starts_with("FileTransport"));
+
+        assert!(transport_for("list.automerge", None).is_err());
+        assert!(transport_for("ftp://example.com/list", None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_file_is_overwritten_by_every_push() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let remote = Remote::new(dir.join("list.automerge").to_str().unwrap().to_string(), None, None).unwrap();
+
+        assert!(remote.fetch().unwrap().is_empty());
+        assert!(remote.fetch_signatures().unwrap().is_empty());
+        remote.push("alice", b"alice's").unwrap();
+        remote.push("bob", b"bob's").unwrap();
+        assert_eq!(remote.fetch().unwrap(), vec![b"bob's".to_vec()]);
+
+        remote.push_signatures("bob", &Signatures::new()).unwrap();
+        assert!(dir.join("list.automerge.sig").exists());
+        assert!(remote.fetch_signatures().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_directory_keeps_a_file_per_peer() {
+        let dir = temp_dir();
+        let remote = Remote::new(format!("{}/", dir.display()), None, None).unwrap();
+
+        remote.push("alice", b"alice's").unwrap();
+        remote.push("bob", b"bob's").unwrap();
+        remote.push("alice", b"alice's again").unwrap();
+        remote.push_signatures("alice", &Signatures::new()).unwrap();
+        // Neither signatures nor anything else in there are documents
+        fs::write(dir.join("notes.txt"), "not a document").unwrap();
+
+        let mut copies = remote.fetch().unwrap();
+        copies.sort();
+        assert_eq!(copies, vec![b"alice's again".to_vec(), b"bob's".to_vec()]);
+        assert!(dir.join("alice.automerge.sig").exists());
+        assert!(!dir.join("alice.automerge.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_encrypted_remote_only_holds_ciphertext() {
+        let dir = temp_dir();
+        let location = format!("{}/", dir.display());
+        let remote = Remote::new(location.clone(), None, Some("correct horse".to_string())).unwrap();
+        assert!(remote.is_encrypted());
+
+        remote.push("alice", b"secret list").unwrap();
+        let stored = fs::read(dir.join("alice.automerge")).unwrap();
+        assert!(crypto::is_encrypted(&stored));
+        assert_eq!(remote.fetch().unwrap(), vec![b"secret list".to_vec()]);
+
+        assert!(Remote::new(location.clone(), None, None).unwrap().fetch().is_err());
+        assert!(Remote::new(location, None, Some("wrong".to_string())).unwrap().fetch().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use vgtk::lib::gtk::*;
+use vgtk::{gtk, gtk_if, VNode};
+
+use todomvc::items::{Item, Problem, DUE_FORMAT};
+use std::collections::HashSet;
+
+use chrono::TimeZone;
+use todomvc::outbox::Pending;
+use todomvc::remote::Remote;
+use todomvc::status::SyncStatus;
+
+use crate::app::{Model, Msg};
+
+/// Starts the widget name of each item's row, followed by the item's id, so
+/// keyboard focus can be traced back to the item
+pub const ITEM_ROW: &str = "item:";
+
+/// `focused` moves the keyboard to the row, `editing` shows entries to edit
+/// the item's task, due date and how often it repeats in
+pub fn item_row(item: &Item, focused: bool, editing: bool) -> VNode<Model> {
+    let id = item.id.clone();
+    let remove_id = item.id.clone();
+    let restore_id = item.id.clone();
+    let rename_id = item.id.clone();
+    let recurrence_id = item.id.clone();
+    let due_id = item.id.clone();
+    let mut label = if item.done {
+        format!(
+            "<span strikethrough=\"true\" alpha=\"50%\">{}</span>",
+            item.task
+        )
+    } else {
+        item.task.clone()
+    };
+    let mut when = Vec::new();
+    if let Some(recurrence) = &item.recurrence {
+        when.push(format!("repeats {}", recurrence.describe()));
+    }
+    if let Some(due) = item.due {
+        when.push(format!("due {}", due.format("%a %-d %b")));
+    }
+    if !when.is_empty() {
+        label.push_str(&format!(" <span size=\"small\" alpha=\"60%\">{}</span>", when.join(" · ")));
+    }
+    let rule = item.recurrence.as_ref().map(ToString::to_string).unwrap_or_default();
+    let due = item.due.map(|due| due.format(DUE_FORMAT).to_string()).unwrap_or_default();
+    gtk! {
+        <ListBoxRow widget_name=format!("{}{}", ITEM_ROW, item.id) property_has_focus=focused>
+            <Box spacing=10 orientation=Orientation::Horizontal>
+                <CheckButton active=item.done on toggled=|_| Msg::Toggle { id: id.clone() } />
+                {
+                    if editing {
+                        gtk! {
+                            <Box spacing=10 orientation=Orientation::Horizontal Box::expand=true Box::fill=true>
+                                <Entry text=item.task.clone() property_has_focus=true Box::expand=true Box::fill=true
+                                       on activate=|entry| Msg::Rename {
+                                           id: rename_id.clone(),
+                                           task: entry.get_text().to_string(),
+                                       } />
+                                <Entry text=due placeholder_text="Due: YYYY-MM-DD" width_chars=16
+                                       on activate=|entry| Msg::SetDue {
+                                           id: due_id.clone(),
+                                           due: entry.get_text().to_string(),
+                                       } />
+                                <Entry text=rule placeholder_text="Repeats: daily, weekly on mon, thu or monthly"
+                                       width_chars=30
+                                       on activate=|entry| Msg::SetRecurrence {
+                                           id: recurrence_id.clone(),
+                                           rule: entry.get_text().to_string(),
+                                       } />
+                            </Box>
+                        }
+                    } else {
+                        gtk! {
+                            <Label label=label use_markup=true Box::fill=true />
+                        }
+                    }
+                }
+                {
+                    if item.is_deleted() {
+                        gtk! {
+                            <Button Box::pack_type=PackType::End relief=ReliefStyle::None
+                                    always_show_image=true image="edit-undo" tooltip_text="Restore"
+                                    on clicked=|_| Msg::Restore { id: restore_id.clone() } />
+                        }
+                    } else {
+                        gtk! {
+                            <Button Box::pack_type=PackType::End relief=ReliefStyle::None
+                                    always_show_image=true image="edit-delete" tooltip_text="Move to trash"
+                                    on clicked=|_| Msg::Remove { id: remove_id.clone() } />
+                        }
+                    }
+                }
+            </Box>
+        </ListBoxRow>
+    }
+}
+
+pub fn problem_row(problem: &Problem) -> VNode<Model> {
+    let label = match (problem.index, &problem.object_id) {
+        (Some(index), Some(object_id)) => format!("Item {} ({}): {}", index, object_id, problem.reason),
+        (Some(index), None) => format!("Item {}: {}", index, problem.reason),
+        (None, _) => problem.reason.clone(),
+    };
+    match problem.index {
+        Some(index) => {
+            let object_id = problem.object_id.clone();
+            let delete_object_id = problem.object_id.clone();
+            gtk! {
+                <ListBoxRow>
+                    <Box spacing=10 orientation=Orientation::Horizontal>
+                        <Label label=label line_wrap=true Box::fill=true />
+                        <Button label="Delete" Box::pack_type=PackType::End
+                                on clicked=|_| Msg::DeleteProblem { index, object_id: delete_object_id.clone() } />
+                        <Button label="Repair" Box::pack_type=PackType::End
+                                on clicked=|_| Msg::RepairProblem { index, object_id: object_id.clone() } />
+                    </Box>
+                </ListBoxRow>
+            }
+        }
+        None => gtk! {
+            <ListBoxRow>
+                <Label label=label line_wrap=true />
+            </ListBoxRow>
+        },
+    }
+}
+
+/// `live` is the state of live sync with the remote, if it's on, `pending`
+/// the push waiting in the outbox for it, if there is one, and `ours` the
+/// hashes of every change we hold
+pub fn remote_row(
+    remote: &Remote,
+    index: usize,
+    live: Option<&str>,
+    status: &SyncStatus,
+    pending: Option<&Pending>,
+    ours: &HashSet<String>,
+) -> VNode<Model> {
+    let can_go_live = live.is_none() && !remote.is_encrypted() && todomvc::live::live_url(&remote.location).is_some();
+    let mut summary = vec![status.divergence(ours).to_string()];
+    if let Some(pull) = &status.last_pull {
+        summary.push(format!("pulled {}", pull));
+    }
+    if let Some(push) = &status.last_push {
+        summary.push(format!("pushed {}", push));
+    }
+    if let Some(pending) = pending {
+        let retry = chrono::Local.timestamp(pending.next_attempt, 0);
+        summary.push(format!("push queued, {} tries, next at {}", pending.attempts, retry.format("%H:%M")));
+    }
+    let mut errors: Vec<String> = status
+        .last_pull
+        .iter()
+        .chain(status.last_push.iter())
+        .filter_map(|attempt| attempt.error.clone())
+        .collect();
+    // After a restart the outbox is all we know about the last push
+    if let (None, Some(pending)) = (&status.last_push, pending) {
+        errors.push(pending.last_error.clone());
+    }
+    gtk! {
+        <Box spacing=10 orientation=Orientation::Horizontal>
+            <Box spacing=2 orientation=Orientation::Vertical>
+                <Label label=remote.location.clone() xalign=0.0 />
+                <Label label=summary.join(" · ") xalign=0.0 tooltip_text=errors.join("\n") />
+            </Box>
+            <Button label="pull" on clicked=|_| { Msg::PullFromRemote { remote_index: index }}/>
+            <Button label="preview pull" on clicked=|_| { Msg::PreviewPull { remote_index: index }}/>
+            <Button label="push" on clicked=|_| { Msg::PushToRemote { remote_index: index }}/>
+            {
+                gtk_if!(can_go_live => {
+                    <Button label="live" on clicked=|_| { Msg::GoLive { remote_index: index }}/>
+                })
+            }
+            {
+                live.map(|status| gtk! { <Label label=status.to_string() /> })
+            }
+        </Box>
+    }
+}
+
+pub fn peer_row(address: &str, status: &str) -> VNode<Model> {
+    gtk! {
+        <Box spacing=10 orientation=Orientation::Horizontal>
+            <Label label=address.to_string() />
+            <Label label=status.to_string() />
+        </Box>
+    }
+}
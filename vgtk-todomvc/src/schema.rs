@@ -0,0 +1,149 @@
+use std::fmt;
+
+use automerge::{InvalidChangeRequest, LocalChange, MutableDocument, Path, ScalarValue, Value};
+
+use crate::document::Document;
+
+/// The version of the document shape this build reads and writes
+pub const SCHEMA_VERSION: i64 = 1;
+
+/// A step which upgrades a document from version `from` to `from + 1`.
+///
+/// `migrate` writes the new `schemaVersion` in the same change as the
+/// migration's own edits, so `apply` only has to reshape the data.
+struct Migration {
+    from: i64,
+    description: &'static str,
+    apply: fn(&mut dyn MutableDocument) -> Result<(), InvalidChangeRequest>,
+}
+
+/// Every migration, in order. Documents written before versioning existed
+/// have no `schemaVersion` key and are treated as version 0, which has the
+/// same shape as version 1.
+static MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    description: "Add schemaVersion",
+    apply: stamp_only,
+}];
+
+fn stamp_only(_doc: &mut dyn MutableDocument) -> Result<(), InvalidChangeRequest> {
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub enum SchemaError {
+    /// The document was written by a newer version of the application
+    TooNew { found: i64 },
+    /// `schemaVersion` is not a number, or no migration exists for it
+    Invalid(String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::TooNew { found } => write!(
+                f,
+                "This document was written by a newer schema (version {}, this application understands up to version {}). Please upgrade to open it.",
+                found, SCHEMA_VERSION
+            ),
+            SchemaError::Invalid(reason) => write!(f, "Invalid document schema: {}", reason),
+        }
+    }
+}
+
+/// Reads the schema version of a document's root map
+pub fn version_of(state: &Value) -> Result<i64, SchemaError> {
+    let values = match state {
+        Value::Map(values, automerge::MapType::Map) => values,
+        _ => return Err(SchemaError::Invalid("document root is not a map".to_string())),
+    };
+    match values.get("schemaVersion") {
+        None => Ok(0),
+        Some(Value::Primitive(ScalarValue::Int(v))) => Ok(*v),
+        Some(Value::Primitive(ScalarValue::Uint(v))) => Ok(*v as i64),
+        Some(Value::Primitive(ScalarValue::F64(v))) if v.fract() == 0.0 => Ok(*v as i64),
+        Some(other) => Err(SchemaError::Invalid(format!("schemaVersion was {:?}", other))),
+    }
+}
+
+/// Fails if the document was written by a newer schema than we understand
+pub fn check(state: &Value) -> Result<i64, SchemaError> {
+    let version = version_of(state)?;
+    if version > SCHEMA_VERSION {
+        Err(SchemaError::TooNew { found: version })
+    } else {
+        Ok(version)
+    }
+}
+
+/// Upgrades the document to `SCHEMA_VERSION`, writing one local change per
+/// migration. Returns whether anything was written.
+pub fn migrate(document: &mut Document) -> Result<bool, SchemaError> {
+    let mut migrated = false;
+    loop {
+        let version = check(document.state())?;
+        if version == SCHEMA_VERSION {
+            return Ok(migrated);
+        }
+        let migration = MIGRATIONS
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| SchemaError::Invalid(format!("no migration from version {}", version)))?;
+        document
+            .change(Some(migration.description.to_string()), |doc| {
+                (migration.apply)(doc)?;
+                doc.add_change(LocalChange::set(
+                    Path::root().key("schemaVersion"),
+                    Value::Primitive(ScalarValue::Int(version + 1)),
+                ))?;
+                Ok(())
+            })
+            .map_err(SchemaError::Invalid)?;
+        migrated = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped(version: i64) -> Vec<u8> {
+        let mut document = Document::default();
+        document
+            .change(None, |doc| {
+                doc.add_change(LocalChange::set(Path::root().key("schemaVersion"), Value::Primitive(ScalarValue::Int(version))))
+            })
+            .unwrap();
+        document.save().unwrap()
+    }
+
+    #[test]
+    fn refuses_documents_from_a_newer_schema() {
+        let newer = stamped(SCHEMA_VERSION + 1);
+        match Document::load(newer.clone()) {
+            Err(e) => assert!(e.contains("newer schema"), "{}", e),
+            Ok(_) => panic!("loaded a document from a newer schema"),
+        }
+        let mut ours = Document::default();
+        assert!(ours.merge(&newer).is_err());
+        assert_eq!(version_of(ours.state()).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrates_version_0_once() {
+        // New lists aren't stamped until they're migrated
+        let mut document = Document::default();
+        assert_eq!(version_of(document.state()).unwrap(), 0);
+        let fork = document.fork().unwrap();
+        assert_eq!(fork.change_hashes(), document.change_hashes());
+
+        assert!(migrate(&mut document).unwrap());
+        assert_eq!(version_of(document.state()).unwrap(), SCHEMA_VERSION);
+        let changes = document.change_hashes().len();
+        assert!(!migrate(&mut document).unwrap());
+        assert_eq!(document.change_hashes().len(), changes);
+
+        let mut reloaded = Document::load(document.save().unwrap()).unwrap();
+        assert_eq!(version_of(reloaded.state()).unwrap(), SCHEMA_VERSION);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::config_dir;
+
+/// An application action which can be bound to keys
+#[derive(Clone, Copy, Debug)]
+pub struct Shortcut {
+    /// The name of the action, without the `app.` prefix
+    pub action: &'static str,
+    pub description: &'static str,
+    /// The keys it's bound to unless the user says otherwise, in the form
+    /// `gtk::accelerator_parse` reads
+    pub accels: &'static [&'static str],
+}
+
+/// Every action which can be bound, in the order the shortcuts window lists
+/// them
+pub const SHORTCUTS: &[Shortcut] = &[
+    Shortcut { action: "new-item", description: "Type a new item", accels: &["<Ctrl>n"] },
+    Shortcut { action: "next-item", description: "Go to the next item", accels: &["<Ctrl>Down"] },
+    Shortcut { action: "previous-item", description: "Go to the previous item", accels: &["<Ctrl>Up"] },
+    Shortcut { action: "toggle-item", description: "Complete or reopen the item", accels: &["space"] },
+    Shortcut { action: "delete-item", description: "Move the item to the trash", accels: &["Delete"] },
+    Shortcut { action: "edit-item", description: "Edit the item", accels: &["F2"] },
+    Shortcut { action: "cancel-edit", description: "Stop editing without saving", accels: &["Escape"] },
+    Shortcut { action: "filter-all", description: "Show all items", accels: &["<Alt>1"] },
+    Shortcut { action: "filter-active", description: "Show active items", accels: &["<Alt>2"] },
+    Shortcut { action: "filter-completed", description: "Show completed items", accels: &["<Alt>3"] },
+    Shortcut { action: "filter-trash", description: "Show the trash", accels: &["<Alt>4"] },
+    Shortcut { action: "pull-all", description: "Pull from every remote", accels: &["<Ctrl>p"] },
+    Shortcut { action: "push-all", description: "Push to every remote", accels: &["<Ctrl><Shift>p"] },
+    Shortcut { action: "new-list", description: "Start a new list in a tab", accels: &["<Ctrl>t"] },
+    Shortcut { action: "open", description: "Open a list in a tab", accels: &["<Ctrl>o"] },
+    Shortcut { action: "save", description: "Save the list", accels: &["<Ctrl>s"] },
+    Shortcut { action: "save-as", description: "Save the list as a new file", accels: &["<Ctrl><Shift>s"] },
+    Shortcut { action: "close-list", description: "Close the list's tab", accels: &["<Ctrl>w"] },
+    Shortcut { action: "shortcuts", description: "Show keyboard shortcuts", accels: &["<Ctrl>question", "F1"] },
+    Shortcut { action: "quit", description: "Quit", accels: &["<Ctrl>q"] },
+];
+
+/// The keys bound to each action, by action name
+#[derive(Clone, Debug)]
+pub struct Bindings(BTreeMap<&'static str, Vec<String>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings(
+            SHORTCUTS
+                .iter()
+                .map(|shortcut| (shortcut.action, shortcut.accels.iter().map(|a| a.to_string()).collect()))
+                .collect(),
+        )
+    }
+}
+
+impl Bindings {
+    /// Where the user's own bindings are kept, `shortcuts.json` in the
+    /// config directory:
+    ///
+    /// ```json
+    /// {"toggle-item": ["space", "x"], "delete-item": []}
+    /// ```
+    ///
+    /// Each action listed there is bound to exactly the keys given, none
+    /// for an empty list. Actions which aren't listed keep their defaults.
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("shortcuts.json"))
+    }
+
+    /// The default bindings with the overrides at `path` applied, just the
+    /// defaults if there's nothing there
+    pub fn load(path: &Path) -> Result<Bindings, String> {
+        let overrides: BTreeMap<String, Vec<String>> = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let mut bindings = Bindings::default();
+        for (action, accels) in overrides {
+            match SHORTCUTS.iter().find(|shortcut| shortcut.action == action) {
+                Some(shortcut) => {
+                    bindings.0.insert(shortcut.action, accels);
+                }
+                None => return Err(format!("{}: there's no action called {}", path.display(), action)),
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// The keys bound to `action`
+    pub fn accels(&self, action: &str) -> &[String] {
+        self.0.get(action).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_replace_defaults_and_unknown_actions_are_errors() {
+        let dir = std::env::temp_dir().join(format!("todomvc-shortcuts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shortcuts.json");
+
+        assert_eq!(Bindings::load(&path).unwrap().accels("toggle-item"), ["space"]);
+
+        fs::write(&path, r#"{"toggle-item": ["x"], "delete-item": []}"#).unwrap();
+        let bindings = Bindings::load(&path).unwrap();
+        assert_eq!(bindings.accels("toggle-item"), ["x"]);
+        assert!(bindings.accels("delete-item").is_empty());
+        assert_eq!(bindings.accels("quit"), ["<Ctrl>q"]);
+
+        fs::write(&path, r#"{"launch-rockets": ["r"]}"#).unwrap();
+        assert!(Bindings::load(&path).unwrap_err().contains("launch-rockets"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
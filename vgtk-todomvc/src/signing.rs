@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer as _, Verifier as _};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::config_dir;
+
+/// A signature over the hash of one change, by the key of whoever made it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeSignature {
+    /// The hex actor id of the change, so we can check every change from an
+    /// actor was signed by the same key
+    pub actor: String,
+    /// The hex public key which made the signature
+    pub key: String,
+    pub signature: String,
+}
+
+/// Signatures by the hex hash of the change they sign. They're stored
+/// alongside the document, as JSON, wherever it's kept.
+pub type Signatures = BTreeMap<String, ChangeSignature>;
+
+pub fn hash_of(change: &automerge::Change) -> String {
+    hex::encode(change.hash.0)
+}
+
+pub fn parse_signatures(data: &[u8]) -> Result<Signatures, String> {
+    serde_json::from_slice(data).map_err(|e| format!("Invalid signatures: {}", e))
+}
+
+pub fn encode_signatures(signatures: &Signatures) -> Vec<u8> {
+    serde_json::to_vec_pretty(signatures).unwrap()
+}
+
+/// Reads signatures saved with `save_signatures`, none if there's no file
+pub fn load_signatures(path: &Path) -> Result<Signatures, String> {
+    match fs::read(path) {
+        Ok(data) => parse_signatures(&data).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Signatures::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+/// Writes signatures beside `path` and renames them over it
+pub fn save_signatures(path: &Path, signatures: &Signatures) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, encode_signatures(signatures))?;
+    fs::rename(&tmp, path)
+}
+
+/// Signs local changes with this user's key
+pub struct Signer {
+    keypair: Keypair,
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signer({})", self.public_key())
+    }
+}
+
+impl Signer {
+    pub(crate) fn from_secret(secret: &[u8]) -> Result<Signer, String> {
+        let secret = SecretKey::from_bytes(secret).map_err(|e| e.to_string())?;
+        let public = PublicKey::from(&secret);
+        Ok(Signer {
+            keypair: Keypair { secret, public },
+        })
+    }
+
+    /// This user's key, from `signing-key` in the config directory, created
+    /// the first time it's asked for. Only the user may read the file.
+    pub fn load_or_create() -> Result<Signer, String> {
+        let path = config_dir().ok_or("no config directory")?.join("signing-key");
+        match fs::read(&path) {
+            Ok(secret) => Signer::from_secret(&secret).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut secret = [0; 32];
+                OsRng.fill_bytes(&mut secret);
+                let write = || -> io::Result<()> {
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)?.write_all(&secret)
+                };
+                write().map_err(|e| format!("{}: {}", path.display(), e))?;
+                Signer::from_secret(&secret)
+            }
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// The hex public key, to give to collaborators for their keyrings
+    pub fn public_key(&self) -> String {
+        hex::encode(self.keypair.public.to_bytes())
+    }
+
+    pub fn sign(&self, change: &automerge::Change) -> ChangeSignature {
+        ChangeSignature {
+            actor: change.actor_id.to_hex_string(),
+            key: self.public_key(),
+            signature: hex::encode(self.keypair.sign(&change.hash.0).to_bytes()),
+        }
+    }
+}
+
+/// The public keys whose changes we trust, by who they belong to, kept in
+/// `keyring.json` in the config directory:
+///
+/// ```json
+/// {"trusted": {"alice": "<hex public key>"}, "reject_unknown": true}
+/// ```
+///
+/// Our own key is always trusted. Changes with a bad signature are always
+/// rejected, changes which are unsigned or signed by someone not in the
+/// keyring only if `reject_unknown` is set.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Keyring {
+    #[serde(default)]
+    pub trusted: BTreeMap<String, String>,
+    #[serde(default)]
+    pub reject_unknown: bool,
+}
+
+impl Keyring {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("keyring.json"))
+    }
+
+    /// Reads the keyring at `path`, an empty one if there's nothing there
+    pub fn load(path: &Path) -> Result<Keyring, String> {
+        match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Keyring::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Who a hex public key belongs to, if we trust it
+    fn owner(&self, key: &str) -> Option<&str> {
+        self.trusted
+            .iter()
+            .find(|(_, trusted)| trusted.eq_ignore_ascii_case(key))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Why a change pulled from a remote wasn't merged, or was merged despite
+/// not being signed by anyone we trust
+#[derive(Clone, Debug)]
+pub struct Flagged {
+    pub hash: String,
+    pub reason: String,
+    pub rejected: bool,
+}
+
+/// Checks the signatures of changes pulled from a remote before they're
+/// applied. `known` are the signatures we already hold, so an actor can't be
+/// claimed by a second key. `own_key` is our own hex public key.
+///
+/// Returns the changes to apply and the ones which were flagged. A change
+/// which depends on a rejected one is rejected too.
+pub fn verify(
+    changes: Vec<automerge::Change>,
+    signatures: &Signatures,
+    known: &Signatures,
+    keyring: &Keyring,
+    own_key: Option<&str>,
+) -> (Vec<automerge::Change>, Vec<Flagged>) {
+    let mut actor_keys: BTreeMap<&str, &str> = known.values().map(|s| (s.actor.as_str(), s.key.as_str())).collect();
+    let mut rejected: HashSet<[u8; 32]> = HashSet::new();
+    let mut accepted = Vec::new();
+    let mut flagged = Vec::new();
+    // Changes are in causal order, so dependencies are checked first
+    for change in changes {
+        let hash = hash_of(&change);
+        let mut flag = |reason: String, reject: bool| {
+            flagged.push(Flagged {
+                hash: hash.clone(),
+                reason,
+                rejected: reject,
+            })
+        };
+        if change.deps.iter().any(|dep| rejected.contains(&dep.0)) {
+            flag("depends on a rejected change".to_string(), true);
+            rejected.insert(change.hash.0);
+            continue;
+        }
+        let actor = change.actor_id.to_hex_string();
+        let problem = match signatures.get(&hash).or_else(|| known.get(&hash)) {
+            None => Some(("unsigned".to_string(), keyring.reject_unknown)),
+            Some(signature) => match check(&change, &actor, signature) {
+                Err(reason) => Some((reason, true)),
+                Ok(()) => match actor_keys.get(actor.as_str()) {
+                    Some(key) if !key.eq_ignore_ascii_case(&signature.key) => {
+                        Some(("actor is already signed for by a different key".to_string(), true))
+                    }
+                    _ => {
+                        actor_keys.insert(signature.actor.as_str(), signature.key.as_str());
+                        let trusted = own_key.map_or(false, |own| own.eq_ignore_ascii_case(&signature.key))
+                            || keyring.owner(&signature.key).is_some();
+                        if trusted {
+                            None
+                        } else {
+                            Some((format!("signed by unknown key {}", signature.key), keyring.reject_unknown))
+                        }
+                    }
+                },
+            },
+        };
+        match problem {
+            Some((reason, true)) => {
+                flag(reason, true);
+                rejected.insert(change.hash.0);
+            }
+            Some((reason, false)) => {
+                flag(reason, false);
+                accepted.push(change);
+            }
+            None => accepted.push(change),
+        }
+    }
+    (accepted, flagged)
+}
+
+/// Checks that `signature` is a valid signature of `change` for its actor
+fn check(change: &automerge::Change, actor: &str, signature: &ChangeSignature) -> Result<(), String> {
+    if signature.actor != actor {
+        return Err("signature is for a different actor".to_string());
+    }
+    let key = hex::decode(&signature.key)
+        .ok()
+        .and_then(|key| PublicKey::from_bytes(&key).ok())
+        .ok_or("invalid public key")?;
+    let bytes = hex::decode(&signature.signature).map_err(|_| "invalid signature")?;
+    let parsed = Signature::try_from(bytes.as_slice()).map_err(|_| "invalid signature")?;
+    key.verify(&change.hash.0, &parsed).map_err(|_| "forged signature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::document::Document;
+    use crate::items::Item;
+
+    fn signer(seed: u8) -> Signer {
+        Signer::from_secret(&[seed; 32]).unwrap()
+    }
+
+    /// `count` changes made by `signer`, in causal order, without the
+    /// initial change every document starts from, and their signatures
+    fn signed_changes(signer: Signer, count: usize) -> (Vec<automerge::Change>, Signatures) {
+        let mut document = Document::default();
+        let initial = document.change_hashes();
+        document.set_signer(Arc::new(signer));
+        for index in 0..count {
+            document.add(&Item::new(format!("item {}", index)));
+        }
+        let changes = automerge::Change::load_document(&document.save().unwrap())
+            .unwrap()
+            .into_iter()
+            .filter(|change| !initial.contains(&hash_of(change)))
+            .collect();
+        (changes, document.signatures().clone())
+    }
+
+    fn trusting(signer: &Signer) -> Keyring {
+        Keyring {
+            trusted: vec![("alice".to_string(), signer.public_key())].into_iter().collect(),
+            reject_unknown: false,
+        }
+    }
+
+    fn reasons(flagged: &[Flagged]) -> Vec<(&str, bool)> {
+        flagged.iter().map(|flag| (flag.reason.as_str(), flag.rejected)).collect()
+    }
+
+    #[test]
+    fn trusted_and_own_signatures_pass() {
+        let (changes, signatures) = signed_changes(signer(1), 2);
+        let (accepted, flagged) = verify(changes.clone(), &signatures, &Signatures::new(), &trusting(&signer(1)), None);
+        assert_eq!((accepted.len(), flagged.len()), (2, 0));
+
+        let own = signer(1).public_key();
+        let (accepted, flagged) = verify(changes, &signatures, &Signatures::new(), &Keyring::default(), Some(&own));
+        assert_eq!((accepted.len(), flagged.len()), (2, 0));
+    }
+
+    #[test]
+    fn a_forged_signature_and_its_dependents_are_rejected() {
+        let (changes, mut signatures) = signed_changes(signer(1), 2);
+        // Claim the first change with the signature of the second
+        let first = hash_of(&changes[0]);
+        let second = signatures[&hash_of(&changes[1])].clone();
+        signatures.insert(first, second);
+
+        let (accepted, flagged) = verify(changes, &signatures, &Signatures::new(), &trusting(&signer(1)), None);
+        assert!(accepted.is_empty());
+        assert_eq!(
+            reasons(&flagged),
+            vec![("forged signature", true), ("depends on a rejected change", true)]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_unsigned_changes_are_rejected_only_if_asked() {
+        let (changes, signatures) = signed_changes(signer(2), 1);
+        let unknown = format!("signed by unknown key {}", signer(2).public_key());
+        let mut keyring = trusting(&signer(1));
+
+        let (accepted, flagged) = verify(changes.clone(), &signatures, &Signatures::new(), &keyring, None);
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(reasons(&flagged), vec![(unknown.as_str(), false)]);
+        let (accepted, flagged) = verify(changes.clone(), &Signatures::new(), &Signatures::new(), &keyring, None);
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(reasons(&flagged), vec![("unsigned", false)]);
+
+        keyring.reject_unknown = true;
+        let (accepted, flagged) = verify(changes.clone(), &signatures, &Signatures::new(), &keyring, None);
+        assert!(accepted.is_empty());
+        assert_eq!(reasons(&flagged), vec![(unknown.as_str(), true)]);
+        let (accepted, flagged) = verify(changes, &Signatures::new(), &Signatures::new(), &keyring, None);
+        assert!(accepted.is_empty());
+        assert_eq!(reasons(&flagged), vec![("unsigned", true)]);
+    }
+
+    #[test]
+    fn an_actor_can_only_be_signed_for_by_one_key() {
+        let (changes, signatures) = signed_changes(signer(1), 2);
+        // We already hold alice's signature for her first change, and
+        // mallory signs her second with his own key, claiming her actor
+        let first = hash_of(&changes[0]);
+        let known: Signatures = vec![(first.clone(), signatures[&first].clone())].into_iter().collect();
+        let mallory = signer(3);
+        let forged = mallory.sign(&changes[1]);
+        assert_eq!(forged.actor, known[&first].actor);
+        let claimed: Signatures = vec![(hash_of(&changes[1]), forged)].into_iter().collect();
+
+        let mut keyring = trusting(&signer(1));
+        keyring.trusted.insert("mallory".to_string(), mallory.public_key());
+        let (accepted, flagged) = verify(changes[1..].to_vec(), &claimed, &known, &keyring, None);
+        assert!(accepted.is_empty());
+        assert_eq!(reasons(&flagged), vec![("actor is already signed for by a different key", true)]);
+    }
+
+    #[test]
+    fn a_signature_for_another_actor_is_rejected() {
+        let (changes, signatures) = signed_changes(signer(1), 1);
+        let (others, _) = signed_changes(signer(1), 1);
+        // A valid signature, but of a change by a different actor
+        let mut moved = signatures.values().next().unwrap().clone();
+        moved.actor = others[0].actor_id.to_hex_string();
+        let claimed: Signatures = vec![(hash_of(&changes[0]), moved)].into_iter().collect();
+
+        let (accepted, flagged) = verify(changes, &claimed, &Signatures::new(), &trusting(&signer(1)), None);
+        assert!(accepted.is_empty());
+        assert_eq!(reasons(&flagged), vec![("signature is for a different actor", true)]);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+
+/// The hex hashes of every change in a saved document, e.g. one fetched
+/// from a remote
+pub fn change_hashes(data: &[u8]) -> Result<HashSet<String>, String> {
+    let changes = crate::document::untrusted(|| automerge::Change::load_document(data).map_err(|e| format!("{:?}", e)))?;
+    Ok(changes.iter().map(crate::signing::hash_of).collect())
+}
+
+/// When a pull or push happened and how it went
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    pub time: DateTime<Local>,
+    pub error: Option<String>,
+}
+
+impl Attempt {
+    pub fn now(error: Option<String>) -> Attempt {
+        Attempt { time: Local::now(), error }
+    }
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.time.format("%H:%M"))?;
+        match &self.error {
+            Some(_) => write!(f, " failed"),
+            None => write!(f, " ok"),
+        }
+    }
+}
+
+/// How a remote's copy compares to ours, like `git status`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// We haven't fetched from or pushed to the remote yet
+    Unknown,
+    InSync,
+    Ahead(usize),
+    Behind(usize),
+    Diverged { ahead: usize, behind: usize },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let changes = |n: usize| if n == 1 { "1 change".to_string() } else { format!("{} changes", n) };
+        match *self {
+            Divergence::Unknown => write!(f, "not synced yet"),
+            Divergence::InSync => write!(f, "in sync"),
+            Divergence::Ahead(ahead) => write!(f, "ahead, {} to push", changes(ahead)),
+            Divergence::Behind(behind) => write!(f, "behind, {} to pull", changes(behind)),
+            Divergence::Diverged { ahead, behind } => {
+                write!(f, "diverged, {} to push, {} to pull", changes(ahead), behind)
+            }
+        }
+    }
+}
+
+/// What we know about syncing with one remote
+#[derive(Clone, Debug, Default)]
+pub struct SyncStatus {
+    pub last_pull: Option<Attempt>,
+    pub last_push: Option<Attempt>,
+    /// The changes the remote held the last time we fetched from or pushed
+    /// to it
+    pub remote_changes: Option<Arc<HashSet<String>>>,
+}
+
+impl SyncStatus {
+    /// Compares the changes the remote last held with `ours`
+    pub fn divergence(&self, ours: &HashSet<String>) -> Divergence {
+        let theirs = match &self.remote_changes {
+            Some(theirs) => theirs,
+            None => return Divergence::Unknown,
+        };
+        let ahead = ours.difference(theirs).count();
+        let behind = theirs.difference(ours).count();
+        match (ahead, behind) {
+            (0, 0) => Divergence::InSync,
+            (ahead, 0) => Divergence::Ahead(ahead),
+            (0, behind) => Divergence::Behind(behind),
+            (ahead, behind) => Divergence::Diverged { ahead, behind },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::document::Document;
+    use crate::items::Item;
+
+    fn hashes(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn last_held(names: &[&str]) -> SyncStatus {
+        SyncStatus {
+            remote_changes: Some(Arc::new(hashes(names))),
+            ..SyncStatus::default()
+        }
+    }
+
+    #[test]
+    fn compares_our_changes_with_the_remotes() {
+        let ours = hashes(&["a", "b", "c"]);
+        let cases = vec![
+            (SyncStatus::default(), Divergence::Unknown, "not synced yet"),
+            (last_held(&["a", "b", "c"]), Divergence::InSync, "in sync"),
+            (last_held(&["a", "b"]), Divergence::Ahead(1), "ahead, 1 change to push"),
+            (last_held(&["a"]), Divergence::Ahead(2), "ahead, 2 changes to push"),
+            (last_held(&["a", "b", "c", "d"]), Divergence::Behind(1), "behind, 1 change to pull"),
+            (last_held(&["a", "b", "c", "d", "e"]), Divergence::Behind(2), "behind, 2 changes to pull"),
+            (last_held(&["a", "d"]), Divergence::Diverged { ahead: 2, behind: 1 }, "diverged, 2 changes to push, 1 to pull"),
+        ];
+        for (status, divergence, text) in cases {
+            assert_eq!(status.divergence(&ours), divergence);
+            assert_eq!(divergence.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn reads_the_change_hashes_of_a_saved_document() {
+        let mut document = Document::default();
+        document.add(&Item::new("milk"));
+        assert_eq!(change_hashes(&document.save().unwrap()).unwrap(), *document.change_hashes());
+        assert!(change_hashes(b"not a document").is_err());
+    }
+
+    #[test]
+    fn shows_how_an_attempt_went() {
+        let mut attempt = Attempt::now(None);
+        let time = attempt.time.format("%H:%M").to_string();
+        assert_eq!(attempt.to_string(), format!("{} ok", time));
+        attempt.error = Some("offline".to_string());
+        assert_eq!(attempt.to_string(), format!("{} failed", time));
+    }
+}
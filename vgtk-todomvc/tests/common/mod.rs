@@ -0,0 +1,102 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for the server to start taking connections
+const STARTUP: Duration = Duration::from_secs(20);
+
+/// `server/server.py` running on localhost, with a data directory of its
+/// own, stopped and cleaned up when it's dropped.
+///
+/// It's run with `python3`, or whatever `SYNC_SERVER_PYTHON` names, which
+/// needs the packages in `server/Pipfile`, e.g. after `pipenv install` in
+/// `server`, `SYNC_SERVER_PYTHON=$(cd ../server && pipenv --py)`.
+pub struct SyncServer {
+    process: Child,
+    address: SocketAddr,
+    dir: PathBuf,
+}
+
+impl SyncServer {
+    /// Starts the server on a free port and waits until it takes
+    /// connections
+    pub fn start() -> SyncServer {
+        let dir = env::temp_dir().join(format!("todomvc-sync-server-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let python = env::var("SYNC_SERVER_PYTHON").unwrap_or_else(|_| "python3".to_string());
+        let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("server").join("server.py");
+        // Flask logs every request, so its output goes to files rather than
+        // pipes nobody reads, which would fill up and stall it
+        let spawned = Command::new(&python)
+            .args(&["-m", "flask", "run", "--host", "127.0.0.1", "--port", &address.port().to_string()])
+            .env("FLASK_APP", &script)
+            .current_dir(&dir)
+            .stdout(File::create(dir.join("server.out")).unwrap())
+            .stderr(File::create(dir.join("server.err")).unwrap())
+            .spawn();
+        let process = match spawned {
+            Ok(process) => process,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                panic!("{} isn't installed, set SYNC_SERVER_PYTHON to a python with server/Pipfile's packages", python)
+            }
+            Err(e) => panic!("{}: {}", python, e),
+        };
+        let mut server = SyncServer { process, address, dir };
+        server.wait_until_up();
+        server
+    }
+
+    fn wait_until_up(&mut self) {
+        let mut waited = Duration::from_secs(0);
+        while TcpStream::connect(self.address).is_err() {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                let output = fs::read_to_string(self.dir.join("server.err")).unwrap_or_default();
+                panic!("server/server.py exited with {} before it started:\n{}", status, output);
+            }
+            assert!(waited < STARTUP, "server/server.py didn't start taking connections on {}", self.address);
+            thread::sleep(Duration::from_millis(100));
+            waited += Duration::from_millis(100);
+        }
+    }
+
+    /// The URL of a document on the server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{}", self.address, path)
+    }
+}
+
+impl Drop for SyncServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A small deterministic random number generator (xorshift64*), so a
+/// failing run can be repeated from its seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
@@ -0,0 +1,133 @@
+//! Several peers make random edits to their own copy of a list and sync
+//! them through `server/server.py` on localhost, the way the application
+//! does, and must all end up with the same list. No push may lose a change
+//! which was already on the server.
+//!
+//! Each run is seeded, set `CONVERGENCE_SEED` to repeat a failing one. The
+//! server needs a python with its packages, see `common::SyncServer`.
+
+mod common;
+
+use std::collections::HashSet;
+use std::env;
+
+use todomvc::document::Document;
+use todomvc::items::Item;
+use todomvc::remote::Remote;
+use todomvc::signing::Keyring;
+use todomvc::status;
+
+use common::{Rng, SyncServer};
+
+const PEERS: usize = 4;
+const OPERATIONS: usize = 300;
+const SEEDS: u64 = 8;
+
+struct Peer {
+    name: String,
+    document: Document,
+    remote: Remote,
+}
+
+impl Peer {
+    fn pull(&mut self) {
+        let copies = self.remote.fetch().unwrap();
+        let signatures = self.remote.fetch_signatures().unwrap();
+        for data in &copies {
+            let flagged = self.document.merge_verified(data, &signatures, &Keyring::default()).unwrap();
+            assert!(flagged.iter().all(|flag| !flag.rejected), "{}: {:?}", self.name, flagged);
+        }
+    }
+
+    fn push(&mut self) {
+        let before = self.on_server();
+        let flagged = self.remote.push_document(&self.name, &mut self.document, &Keyring::default()).unwrap();
+        assert!(flagged.iter().all(|flag| !flag.rejected), "{}: {:?}", self.name, flagged);
+        let lost = before.difference(&self.on_server()).count();
+        assert_eq!(lost, 0, "{}'s push lost changes which were already on the server", self.name);
+    }
+
+    /// Every change in the copy of the list on the server
+    fn on_server(&self) -> HashSet<String> {
+        let copies = self.remote.fetch().unwrap();
+        copies.iter().flat_map(|data| status::change_hashes(data).unwrap()).collect()
+    }
+
+    /// Picks one of the items at random, if there are any
+    fn pick(&self, rng: &mut Rng) -> Option<String> {
+        let items = self.document.items();
+        if items.is_empty() {
+            None
+        } else {
+            Some(items[rng.below(items.len())].id.clone())
+        }
+    }
+
+    /// The list as a user sees it
+    fn list(&self) -> Vec<(String, String, bool, bool)> {
+        let items = self.document.items();
+        assert!(items.problems().is_empty(), "{}: {:?}", self.name, items.problems());
+        items.iter().map(|item| (item.id.clone(), item.task.clone(), item.done, item.is_deleted())).collect()
+    }
+}
+
+fn run(server: &SyncServer, seed: u64) {
+    let mut rng = Rng::new(seed);
+    let mut peers: Vec<Peer> = (0..PEERS)
+        .map(|i| Peer {
+            name: format!("peer-{}", i),
+            document: Document::default(),
+            remote: Remote::new(server.url(&format!("list-{}", seed)), None, None).unwrap(),
+        })
+        .collect();
+
+    for step in 0..OPERATIONS {
+        let peer = &mut peers[rng.below(PEERS)];
+        match rng.below(8) {
+            0 | 1 => peer.document.add(&Item::new(format!("{} task {}", peer.name, step))),
+            2 => {
+                if let Some(id) = peer.pick(&mut rng) {
+                    peer.document.toggle(&id);
+                }
+            }
+            3 => {
+                if let Some(id) = peer.pick(&mut rng) {
+                    peer.document.remove(&id);
+                }
+            }
+            4 => peer.document.clear_completed(),
+            5 => {
+                if let Some(id) = peer.pick(&mut rng) {
+                    peer.document.restore(&id);
+                }
+            }
+            6 => peer.pull(),
+            _ => peer.push(),
+        }
+    }
+
+    // Everyone pushes what they haven't yet, then fetches what everyone
+    // else pushed
+    for peer in &mut peers {
+        peer.push();
+    }
+    for peer in &mut peers {
+        peer.pull();
+    }
+
+    let expected = peers[0].list();
+    let hashes = peers[0].document.change_hashes();
+    for peer in &peers[1..] {
+        assert_eq!(peer.list(), expected, "{} diverged from {} with seed {}", peer.name, peers[0].name, seed);
+        assert_eq!(peer.document.change_hashes(), hashes, "{} has different history with seed {}", peer.name, seed);
+    }
+}
+
+#[test]
+fn peers_converge_through_a_sync_server() {
+    let server = SyncServer::start();
+    match env::var("CONVERGENCE_SEED") {
+        Ok(seed) => run(&server, seed.parse().expect("CONVERGENCE_SEED should be a number")),
+        Err(_) => (1..=SEEDS).for_each(|seed| run(&server, seed)),
+    }
+}
@@ -0,0 +1,116 @@
+//! Calls the D-Bus interface over a private session bus, with a document
+//! answering calls with the same code as the application.
+//!
+//! Needs `dbus-daemon`, and fails saying so if it isn't installed rather
+//! than passing without having run.
+
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+use zbus::export::zvariant::Type;
+use zbus::{fdo, Connection, Message, MessageType};
+
+use todomvc::dbus::{self, Call, Reply, Todos};
+use todomvc::document::Document;
+
+/// A bus of our own, stopped when it's dropped
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn start() -> Bus {
+        let spawned = Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut daemon = match spawned {
+            Ok(daemon) => daemon,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                panic!("dbus-daemon isn't installed, it's needed to test the D-Bus interface")
+            }
+            Err(e) => panic!("dbus-daemon: {}", e),
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        Bus {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Answers calls on a document, telling listeners when it changes
+struct Shared {
+    document: Mutex<Document>,
+    connection: Connection,
+}
+
+impl Todos for Shared {
+    fn handle(&self, call: Call) -> Result<Reply, String> {
+        let changes = matches!(call, Call::AddTodo { .. } | Call::Toggle { .. });
+        let reply = dbus::answer(&mut self.document.lock().unwrap(), call)?;
+        if changes {
+            dbus::changed(&self.connection)?;
+        }
+        Ok(reply)
+    }
+}
+
+fn call<B: serde::Serialize + Type>(client: &Connection, method: &str, body: &B) -> zbus::Result<Message> {
+    client.call_method(Some(dbus::NAME), dbus::PATH, Some(dbus::INTERFACE), method, body)
+}
+
+fn list(client: &Connection) -> Vec<(String, String, bool)> {
+    call(client, "ListTodos", &()).unwrap().body().unwrap()
+}
+
+/// Waits for the next Changed signal
+fn changed(client: &Connection) {
+    loop {
+        let message = client.receive_message().unwrap();
+        let header = message.header().unwrap();
+        if header.message_type().unwrap() == MessageType::Signal && header.member().unwrap() == Some("Changed") {
+            return;
+        }
+    }
+}
+
+#[test]
+fn adds_lists_and_toggles_todos() {
+    let bus = Bus::start();
+    let connection = dbus::connect(Some(&bus.address)).unwrap();
+    let shared = Shared {
+        document: Mutex::new(Document::default()),
+        connection: connection.clone(),
+    };
+    dbus::serve(connection, shared).unwrap();
+    // Someone else can't take the name while we have it
+    assert!(dbus::connect(Some(&bus.address)).is_err());
+
+    let client = Connection::new_for_address(&bus.address, true).unwrap();
+    fdo::DBusProxy::new(&client)
+        .unwrap()
+        .add_match(&format!("type='signal',interface='{}',member='Changed'", dbus::INTERFACE))
+        .unwrap();
+
+    let id: String = call(&client, "AddTodo", &("Buy milk",)).unwrap().body().unwrap();
+    changed(&client);
+    assert_eq!(list(&client), vec![(id.clone(), "Buy milk".to_string(), false)]);
+
+    call(&client, "Toggle", &(id.as_str(),)).unwrap();
+    changed(&client);
+    assert_eq!(list(&client), vec![(id.clone(), "Buy milk".to_string(), true)]);
+
+    assert!(call(&client, "Toggle", &("no-such-todo",)).is_err());
+    call(&client, "Sync", &()).unwrap();
+}
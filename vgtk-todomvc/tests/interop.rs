@@ -0,0 +1,130 @@
+//! Checks the Rust application reads and writes the same documents as
+//! `AutomergeTodos` in `react-todomvc`.
+//!
+//! `tests/fixtures/js` holds documents saved by the JS client, each with a
+//! `.json` file listing the todos the JS client sees in it. They're made by
+//! `npm run fixtures` in `react-todomvc`; run it again whenever the JS
+//! client changes how it writes documents.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use automerge::{ScalarValue, Value};
+use serde_derive::Deserialize;
+
+use todomvc::document::Document;
+use todomvc::items::Item;
+
+/// A todo as the JS client sees it
+#[derive(Debug, Deserialize)]
+struct Todo {
+    id: String,
+    value: String,
+    completed: bool,
+}
+
+type List = Vec<(String, String, bool)>;
+
+/// A fixture document and the todos it should decode to. A missing one is
+/// a failure, not a skip, so the checks can't quietly stop running.
+fn fixture(name: &str) -> (Vec<u8>, List) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/js");
+    let read = |file: String| match fs::read(dir.join(&file)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            panic!("{} is missing: run `npm run fixtures` in react-todomvc and commit the result", file)
+        }
+        Err(e) => panic!("{}: {}", file, e),
+    };
+    let data = read(format!("{}.automerge", name));
+    let expected: Vec<Todo> = serde_json::from_slice(&read(format!("{}.json", name))).unwrap();
+    (data, expected.into_iter().map(|todo| (todo.id, todo.value, todo.completed)).collect())
+}
+
+/// The todos as the JS client shows them, which leaves out those in the trash
+fn list(document: &Document) -> List {
+    let items = document.items();
+    assert!(items.problems().is_empty(), "{:?}", items.problems());
+    items
+        .iter()
+        .filter(|item| !item.is_deleted())
+        .map(|item| (item.id.clone(), item.task.clone(), item.done))
+        .collect()
+}
+
+/// Every entry of `todos` must have exactly the keys the JS client writes,
+/// plus the `deletedAt` it reads on those in the trash
+fn assert_js_shape(document: &mut Document) {
+    let wanted: BTreeSet<&str> = ["id", "value", "completed"].iter().copied().collect();
+    let todos = match document.state() {
+        Value::Map(root, _) => match root.get("todos") {
+            Some(Value::Sequence(todos)) => todos.clone(),
+            other => panic!("todos is {:?}", other),
+        },
+        other => panic!("root is {:?}", other),
+    };
+    for todo in todos {
+        match todo {
+            Value::Map(values, _) => {
+                let mut keys: BTreeSet<&str> = values.keys().map(String::as_str).collect();
+                if let Some(deleted_at) = values.get("deletedAt") {
+                    assert!(matches!(deleted_at, Value::Primitive(ScalarValue::Int(_))), "deletedAt is {:?}", deleted_at);
+                    keys.remove("deletedAt");
+                }
+                assert_eq!(keys, wanted);
+            }
+            other => panic!("todo is {:?}", other),
+        }
+    }
+}
+
+/// Loads a JS fixture, edits it from Rust, saves it and checks the result
+/// reads back the same, both on its own and merged into the JS original
+fn round_trip(name: &str) {
+    let (data, expected) = fixture(name);
+    let mut document = Document::load(data.clone()).unwrap();
+    assert_eq!(list(&document), expected, "{} decodes differently from the JS client", name);
+
+    document.add(&Item::new("added from Rust"));
+    let first = document.items()[0].id.clone();
+    document.toggle(&first);
+    document.clear_completed();
+    let edited = list(&document);
+    assert_js_shape(&mut document);
+
+    let saved = document.save().unwrap();
+    let mut reloaded = Document::load(saved.clone()).unwrap();
+    assert_eq!(list(&reloaded), edited, "{} doesn't read back after saving from Rust", name);
+    assert_js_shape(&mut reloaded);
+
+    let mut original = Document::load(data).unwrap();
+    original.merge(&saved).unwrap();
+    assert_eq!(list(&original), edited, "{} doesn't merge back into the JS original", name);
+}
+
+#[test]
+fn add() {
+    round_trip("add");
+}
+
+#[test]
+fn toggle() {
+    round_trip("toggle");
+}
+
+#[test]
+fn edit() {
+    round_trip("edit");
+}
+
+#[test]
+fn clear_completed() {
+    round_trip("clear_completed");
+}
+
+#[test]
+fn concurrent_edits() {
+    round_trip("concurrent");
+}
@@ -0,0 +1,158 @@
+//! Feeds malformed, truncated and adversarial documents through everything
+//! which handles bytes from a remote: loading, merging, decoding single
+//! changes and decoding the result into `Items`. All of it has to come back
+//! with an error rather than panic or hang.
+//!
+//! The fuzz targets in `fuzz/` exercise the same entry points.
+
+use automerge::{LocalChange, Path, ScalarValue, Value};
+use proptest::collection::{hash_map, vec};
+use proptest::prelude::*;
+
+use todomvc::document::{self, Document};
+use todomvc::items::Item;
+use todomvc::signing::{Keyring, Signatures};
+
+/// A saved document with a few items, some of them completed
+fn saved(tasks: &[(String, bool)]) -> Vec<u8> {
+    let mut document = Document::default();
+    for (task, done) in tasks {
+        let mut item = Item::new(task.clone());
+        item.done = *done;
+        document.add(&item);
+    }
+    document.save().unwrap()
+}
+
+fn tasks() -> impl Strategy<Value = Vec<(String, bool)>> {
+    vec((".{0,20}", any::<bool>()), 0..5)
+}
+
+/// Any value a document could hold, a few levels deep
+fn value() -> impl Strategy<Value = Value> {
+    let scalar = prop_oneof![
+        ".{0,10}".prop_map(|s| Value::Primitive(ScalarValue::Str(s))),
+        any::<i64>().prop_map(|n| Value::Primitive(ScalarValue::Int(n))),
+        any::<f64>().prop_map(|n| Value::Primitive(ScalarValue::F64(n))),
+        any::<bool>().prop_map(|b| Value::Primitive(ScalarValue::Boolean(b))),
+        Just(Value::Primitive(ScalarValue::Null)),
+    ];
+    scalar.prop_recursive(3, 16, 4, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..4).prop_map(Value::Sequence),
+            hash_map("[a-z]{1,10}|id|value|completed", inner, 0..4)
+                .prop_map(|values| Value::Map(values, automerge::MapType::Map)),
+        ]
+    })
+}
+
+/// Where an edit which bends a document out of the shape the application
+/// expects goes, the way another client or an older version might
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Todos,
+    SchemaVersion,
+    FirstTodo,
+    Field(&'static str),
+}
+
+impl Target {
+    fn path(self) -> Path {
+        match self {
+            Target::Todos => Path::root().key("todos"),
+            Target::SchemaVersion => Path::root().key("schemaVersion"),
+            Target::FirstTodo => Path::root().key("todos").index(0),
+            Target::Field(field) => Path::root().key("todos").index(0).key(field),
+        }
+    }
+}
+
+fn schema_variant() -> impl Strategy<Value = (Target, Value)> {
+    let target = prop_oneof![
+        Just(Target::Todos),
+        Just(Target::SchemaVersion),
+        Just(Target::FirstTodo),
+        Just(Target::Field("value")),
+        Just(Target::Field("completed")),
+        Just(Target::Field("id")),
+    ];
+    (target, value())
+}
+
+/// What a remote might send: garbage, or a real document which has been
+/// truncated, had bytes flipped or had garbage spliced in
+fn remote_bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        vec(any::<u8>(), 0..512),
+        (tasks(), any::<prop::sample::Index>()).prop_map(|(tasks, at)| {
+            let data = saved(&tasks);
+            data[..at.index(data.len() + 1)].to_vec()
+        }),
+        (tasks(), vec((any::<prop::sample::Index>(), any::<u8>()), 1..8)).prop_map(|(tasks, flips)| {
+            let mut data = saved(&tasks);
+            for (at, byte) in flips {
+                let at = at.index(data.len());
+                data[at] ^= byte;
+            }
+            data
+        }),
+        (tasks(), any::<prop::sample::Index>(), vec(any::<u8>(), 0..64)).prop_map(|(tasks, at, garbage)| {
+            let mut data = saved(&tasks);
+            let at = at.index(data.len() + 1);
+            data.splice(at..at, garbage);
+            data
+        }),
+    ]
+}
+
+/// Every way the application takes in a document, none of which may panic
+fn take_in(data: &[u8]) {
+    if let Ok(loaded) = Document::load(data.to_vec()) {
+        loaded.items().problems();
+    }
+    let mut ours = Document::default();
+    ours.add(&Item::new("ours"));
+    if ours.merge_verified(data, &Signatures::new(), &Keyring::default()).is_ok() {
+        ours.items().problems();
+    }
+    let _ = document::decode_change(data.to_vec());
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 256,
+        // A document which makes loading hang is as bad as one which panics
+        timeout: 10_000,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn malformed_bytes_are_errors(data in remote_bytes()) {
+        take_in(&data);
+    }
+
+    #[test]
+    fn schema_variants_decode_with_problems(tasks in tasks(), edits in vec(schema_variant(), 1..4)) {
+        let mut other = Document::load(saved(&tasks)).unwrap();
+        for (target, value) in edits {
+            // Edits to paths which no longer exist are refused, which is fine
+            let _ = other.change(None, |doc| doc.add_change(LocalChange::set(target.path(), value)));
+        }
+        let data = other.save().unwrap();
+        take_in(&data);
+
+        // Whatever is in there is decoded or reported, never dropped
+        let items = other.items();
+        let todos = match other.state() {
+            Value::Map(root, _) => match root.get("todos") {
+                Some(Value::Sequence(todos)) => Some(todos.len()),
+                _ => None,
+            },
+            _ => None,
+        };
+        match todos {
+            Some(len) => prop_assert_eq!(items.len() + items.problems().len(), len),
+            None => prop_assert!(items.is_empty() && !items.problems().is_empty()),
+        }
+    }
+}